@@ -0,0 +1,33 @@
+//! Frames off a noisy SPI line. Run with `cargo fuzz run pn532_frame` from
+//! `src-tauri`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// the app is a binary, so the frame module is built in directly
+#[path = "../../src/frame.rs"]
+#[allow(dead_code)]
+mod frame;
+
+use frame::Frame;
+
+fuzz_target!(|data: &[u8]| {
+    // decoding whatever comes back must never panic, and anything it makes
+    // of it encodes back to the same frame
+    if let Ok(Frame::Data(decoded)) = frame::decode(data) {
+        let encoded = frame::encode(&decoded).expect("decoded data didn't encode");
+        assert_eq!(frame::decode(&encoded), Ok(Frame::Data(decoded)));
+    }
+
+    // and anything encoded decodes back to itself, with preamble or not
+    if let Ok(encoded) = frame::encode(data) {
+        let expected = match data {
+            [0x7F] => Frame::Error,
+            _ => Frame::Data(data.to_vec()),
+        };
+        assert!(encoded.len() <= frame::encoded_len(data.len()));
+        assert_eq!(frame::decode(&encoded), Ok(expected.clone()));
+        assert_eq!(frame::decode(&encoded[1..]), Ok(expected));
+    }
+});
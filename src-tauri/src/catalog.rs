@@ -0,0 +1,472 @@
+//! The fish catalog. By default this is `catalog/fish.json`, bundled into the
+//! binary at compile time, but the kiosk config can point at a catalog file on
+//! disk instead. Paths in the catalog (images etc.) are relative to the
+//! content directory, which defaults to the folder the catalog file is in.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::catch::Catch;
+use crate::config::KioskConfig;
+use crate::content;
+use crate::food_web::{self, NodeRef, Organism};
+use crate::geo::Geometry;
+use crate::i18n::{Language, Localized};
+use crate::measurement::{Measurement, UnitSystem};
+
+const BUNDLED_CATALOG: &str = include_str!("../catalog/fish.json");
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FishInfo {
+    pub id: u32,
+    pub name: Localized,
+    pub scientific_name: String,
+    pub family: String,
+    pub order: String,
+    pub size: Measurement,
+    pub weight: Measurement,
+    pub lifespan: Measurement,
+    pub habitat: Localized,
+    /// GeoJSON polygon(s) of where the species is found, see `geo`
+    #[serde(default)]
+    pub habitat_range: Option<Geometry>,
+    #[serde(default)]
+    pub depth_range: Option<Measurement>,
+    pub diet: Localized,
+    pub endangered_status: Localized,
+    pub blurb: Localized,
+    pub image_path: String,
+    pub fun_fact: Localized,
+    /// Gallery photos, video loops and narration beyond the main image
+    #[serde(default)]
+    pub media: Vec<Media>,
+    /// Ids of related species, for "you might also catch..."
+    #[serde(default)]
+    pub related: Vec<u32>,
+    /// What it eats, see `food_web`
+    #[serde(default)]
+    pub prey: Vec<NodeRef>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Image,
+    Video,
+    /// Recorded reading of the blurb
+    Narration,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Media {
+    pub kind: MediaKind,
+    /// Relative to the content directory
+    pub path: String,
+    #[serde(default)]
+    pub caption: Option<Localized>,
+    #[serde(default)]
+    pub credit: Option<String>,
+    #[serde(default)]
+    pub licence: Option<String>,
+    /// Language that's spoken, for narration. Narration without a language
+    /// is played whatever the display language is.
+    #[serde(default)]
+    pub language: Option<Language>,
+}
+
+#[derive(Deserialize)]
+struct CatalogFile {
+    fish: Vec<FishInfo>,
+    /// Non-fish members of the food web
+    #[serde(default)]
+    organisms: Vec<Organism>,
+}
+
+pub struct Catalog {
+    fish: Vec<FishInfo>,
+    organisms: Vec<Organism>,
+    content_dir: PathBuf,
+}
+
+impl Catalog {
+    /// `bundled_content_dir` is where the bundled catalog's images were
+    /// installed (the app's resource directory).
+    pub fn from_config(config: &KioskConfig, bundled_content_dir: PathBuf) -> Self {
+        let catalog = match &config.catalog_path {
+            Some(path) => match Self::load(path) {
+                Ok(catalog) => catalog,
+                Err(e) => {
+                    eprintln!("Catalog: {} - falling back to bundled catalog", e);
+                    Self::bundled(bundled_content_dir)
+                }
+            },
+            None => Self::bundled(bundled_content_dir),
+        };
+
+        match &config.content_dir {
+            Some(content_dir) => Self {
+                content_dir: content_dir.clone(),
+                ..catalog
+            },
+            None => catalog,
+        }
+    }
+
+    pub fn bundled(content_dir: PathBuf) -> Self {
+        Self::from_json(BUNDLED_CATALOG, content_dir).expect("bundled fish catalog is invalid")
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {} - {}", path.display(), e))?;
+        let content_dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+
+        let catalog = Self::from_json(&json, content_dir)
+            .map_err(|e| format!("Failed to parse {} - {}", path.display(), e))?;
+        println!(
+            "Catalog: Loaded {} fish from {}",
+            catalog.fish.len(),
+            path.display()
+        );
+        Ok(catalog)
+    }
+
+    fn from_json(json: &str, content_dir: PathBuf) -> Result<Self, String> {
+        let file: CatalogFile = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let catalog = Self {
+            fish: file.fish,
+            organisms: file.organisms,
+            content_dir,
+        };
+        catalog.validate()?;
+        Ok(catalog)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for (i, fish) in self.fish.iter().enumerate() {
+            if self.fish[..i].iter().any(|f| f.id == fish.id) {
+                return Err(format!("Fish ID '{}' - Duplicate id", fish.id));
+            }
+
+            for (name, m) in [
+                ("Size", &fish.size),
+                ("Weight", &fish.weight),
+                ("Lifespan", &fish.lifespan),
+            ] {
+                m.validate()
+                    .map_err(|e| format!("Fish ID '{}' - {} {}", fish.id, name, e))?;
+            }
+
+            if let Some(depth) = &fish.depth_range {
+                depth
+                    .validate()
+                    .map_err(|e| format!("Fish ID '{}' - Depth range {}", fish.id, e))?;
+            }
+
+            if let Some(range) = &fish.habitat_range {
+                range
+                    .validate()
+                    .map_err(|e| format!("Fish ID '{}' - Habitat range {}", fish.id, e))?;
+            }
+
+            for &related in &fish.related {
+                if related == fish.id {
+                    return Err(format!("Fish ID '{}' - Related to itself", fish.id));
+                }
+                if self.get(related).is_none() {
+                    return Err(format!(
+                        "Fish ID '{}' - Related fish '{}' not found",
+                        fish.id, related
+                    ));
+                }
+            }
+        }
+
+        food_web::validate(self)
+    }
+
+    pub fn content_dir(&self) -> &Path {
+        &self.content_dir
+    }
+
+    pub fn get(&self, id: u32) -> Option<&FishInfo> {
+        self.fish.iter().find(|f| f.id == id)
+    }
+
+    pub fn fish(&self) -> &[FishInfo] {
+        &self.fish
+    }
+
+    pub fn organism(&self, id: &str) -> Option<&Organism> {
+        self.organisms.iter().find(|o| o.id == id)
+    }
+
+    pub fn organisms(&self) -> &[Organism] {
+        &self.organisms
+    }
+
+    pub fn related(&self, id: u32) -> Vec<&FishInfo> {
+        self.get(id)
+            .map(|fish| fish.related.iter().filter_map(|&id| self.get(id)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Case-insensitive search on fish common names in every language and
+    /// scientific names, or an exact match on id when the query is a number.
+    pub fn search(&self, query: &str) -> Vec<&FishInfo> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        if let Ok(id) = query.parse::<u32>() {
+            return self.get(id).into_iter().collect();
+        }
+
+        self.fish
+            .iter()
+            .filter(|f| {
+                f.name
+                    .all()
+                    .chain(std::iter::once(f.scientific_name.as_str()))
+                    .any(|name| name.to_lowercase().contains(&query))
+            })
+            .collect()
+    }
+}
+
+/// What actually gets emitted to the frontend as `fishData` (and returned by
+/// the catalog commands): a catalog entry with its text resolved to one
+/// language and its measurements formatted in the kiosk's unit system.
+#[derive(Serialize, Debug, Clone)]
+pub struct FishPayload {
+    id: u32,
+    language: Language,
+    name: String,
+    scientific_name: String,
+    family: String,
+    order: String,
+    size: Measurement,
+    weight: Measurement,
+    lifespan: Measurement,
+    average_size: String,
+    average_weight: String,
+    average_lifespan: String,
+    habitat: String,
+    diet: String,
+    endangered_status: String,
+    // Untranslated status, so the frontend can style the badge by it whatever
+    // language is being shown
+    conservation_status: String,
+    blurb: String,
+    image_path: String,
+    /// `fish://` URL the webview can load the image from
+    image_url: String,
+    fun_fact: String,
+    media: Vec<MediaPayload>,
+    related: Vec<u32>,
+    /// The individual fish that was scanned, only on `fishData` events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    catch: Option<Catch>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MediaPayload {
+    kind: MediaKind,
+    url: String,
+    caption: Option<String>,
+    credit: Option<String>,
+    licence: Option<String>,
+}
+
+impl FishPayload {
+    pub fn new(fish: &FishInfo, language: Language, units: UnitSystem) -> Self {
+        Self {
+            id: fish.id,
+            language,
+            name: fish.name.get(language).to_string(),
+            scientific_name: fish.scientific_name.clone(),
+            family: fish.family.clone(),
+            order: fish.order.clone(),
+            size: fish.size,
+            weight: fish.weight,
+            lifespan: fish.lifespan,
+            average_size: fish.size.format(units, language),
+            average_weight: fish.weight.format(units, language),
+            average_lifespan: fish.lifespan.format(units, language),
+            habitat: fish.habitat.get(language).to_string(),
+            diet: fish.diet.get(language).to_string(),
+            endangered_status: fish.endangered_status.get(language).to_string(),
+            conservation_status: fish.endangered_status.en.clone(),
+            blurb: fish.blurb.get(language).to_string(),
+            image_path: fish.image_path.clone(),
+            image_url: content::image_url(fish.id),
+            fun_fact: fish.fun_fact.get(language).to_string(),
+            media: media_payload(fish, language),
+            related: fish.related.clone(),
+            catch: None,
+        }
+    }
+
+    pub fn with_catch(self, catch: Catch) -> Self {
+        Self {
+            catch: Some(catch),
+            ..self
+        }
+    }
+}
+
+/// Where a fish lives, for drawing it on a map
+#[derive(Serialize, Debug, Clone)]
+pub struct HabitatPayload {
+    id: u32,
+    habitat: String,
+    range: Option<Geometry>,
+    depth: Option<Measurement>,
+    /// `depth` formatted like the other measurements, e.g. "0 to 3000 feet"
+    depth_text: Option<String>,
+}
+
+impl HabitatPayload {
+    pub fn new(fish: &FishInfo, language: Language, units: UnitSystem) -> Self {
+        Self {
+            id: fish.id,
+            habitat: fish.habitat.get(language).to_string(),
+            range: fish.habitat_range.clone(),
+            depth: fish.depth_range,
+            depth_text: fish.depth_range.map(|d| d.format(units, language)),
+        }
+    }
+}
+
+// Everything in the fish's media list, except narration recorded in a
+// language other than the one being shown. If there's no narration in that
+// language the English recording is used, same as the text falls back.
+fn media_payload(fish: &FishInfo, language: Language) -> Vec<MediaPayload> {
+    let has_narration_in = |language: Language| {
+        fish.media
+            .iter()
+            .any(|m| m.kind == MediaKind::Narration && m.language == Some(language))
+    };
+    let narration_language = if has_narration_in(language) {
+        language
+    } else {
+        Language::En
+    };
+
+    fish.media
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| {
+            m.kind != MediaKind::Narration
+                || m.language.is_none()
+                || m.language == Some(narration_language)
+        })
+        .map(|(index, m)| MediaPayload {
+            kind: m.kind,
+            url: content::media_url(fish.id, index),
+            caption: m.caption.as_ref().map(|c| c.get(language).to_string()),
+            credit: m.credit.clone(),
+            licence: m.licence.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_catalog_is_valid() {
+        Catalog::from_json(BUNDLED_CATALOG, PathBuf::new()).unwrap();
+    }
+
+    #[test]
+    fn bundled_catalog_is_translated() {
+        let catalog = Catalog::from_json(BUNDLED_CATALOG, PathBuf::new()).unwrap();
+        for fish in catalog.fish() {
+            let fields = [
+                ("name", &fish.name),
+                ("habitat", &fish.habitat),
+                ("diet", &fish.diet),
+                ("endangered_status", &fish.endangered_status),
+                ("blurb", &fish.blurb),
+                ("fun_fact", &fish.fun_fact),
+            ];
+            for (field, text) in fields {
+                assert_eq!(text.all().count(), 3, "fish {} {}", fish.id, field);
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate_and_negative_ranges_are_rejected() {
+        let with = |field: &str, range: serde_json::Value| {
+            let mut json: serde_json::Value = serde_json::from_str(BUNDLED_CATALOG).unwrap();
+            json["fish"][0][field] = range;
+            Catalog::from_json(&json.to_string(), PathBuf::new()).map(|_| ())
+        };
+
+        let err = with(
+            "weight",
+            serde_json::json!({ "min": 5, "max": 5, "unit": "pounds" }),
+        );
+        assert_eq!(
+            err,
+            Err("Fish ID '1' - Weight min 5 isn't between 0 and max 5".to_string())
+        );
+        let err = with(
+            "size",
+            serde_json::json!({ "min": -1, "max": 5, "unit": "feet" }),
+        );
+        assert!(err.is_err());
+        let err = with("lifespan", serde_json::json!({ "max": 0, "unit": "years" }));
+        assert!(err.is_err());
+        let ok = with("weight", serde_json::json!({ "max": 1, "unit": "pounds" }));
+        assert_eq!(ok, Ok(()));
+    }
+
+    #[test]
+    fn depth_range_is_checked() {
+        let with_depth = |depth: serde_json::Value| {
+            let mut json: serde_json::Value = serde_json::from_str(BUNDLED_CATALOG).unwrap();
+            json["fish"][0]["depth_range"] = depth;
+            Catalog::from_json(&json.to_string(), PathBuf::new()).map(|_| ())
+        };
+
+        assert_eq!(
+            with_depth(serde_json::json!({ "min": 300, "max": 10, "unit": "feet" })),
+            Err("Fish ID '1' - Depth range min 300 isn't between 0 and max 10".to_string())
+        );
+        assert_eq!(
+            with_depth(serde_json::json!({ "min": -5, "max": 10, "unit": "feet" })),
+            Err("Fish ID '1' - Depth range min -5 isn't between 0 and max 10".to_string())
+        );
+        assert!(with_depth(serde_json::json!({ "max": -10, "unit": "feet" })).is_err());
+        // from the surface down is fine, and so is no depth at all
+        assert_eq!(
+            with_depth(serde_json::json!({ "min": 0, "max": 10, "unit": "feet" })),
+            Ok(())
+        );
+        assert_eq!(with_depth(serde_json::Value::Null), Ok(()));
+    }
+
+    #[test]
+    fn related_fish_must_exist() {
+        let with_related = |related: serde_json::Value| {
+            let mut json: serde_json::Value = serde_json::from_str(BUNDLED_CATALOG).unwrap();
+            json["fish"][0]["related"] = related;
+            Catalog::from_json(&json.to_string(), PathBuf::new()).map(|_| ())
+        };
+
+        assert_eq!(
+            with_related(serde_json::json!([2, 42])),
+            Err("Fish ID '1' - Related fish '42' not found".to_string())
+        );
+        assert_eq!(
+            with_related(serde_json::json!([1])),
+            Err("Fish ID '1' - Related to itself".to_string())
+        );
+        assert_eq!(with_related(serde_json::json!([2, 3])), Ok(()));
+    }
+}
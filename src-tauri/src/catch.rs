@@ -0,0 +1,228 @@
+//! "Your catch": a plausible individual length, weight and age for each scan,
+//! drawn from the species' size, weight and lifespan ranges.
+//!
+//! Size is normally distributed around the middle of the species' range, so
+//! most catches are average and the odd one is a whopper. Weight follows from
+//! length (roughly with its cube, like real fish) and age from size, so a big
+//! fish is also heavy and old rather than the three being drawn separately.
+//!
+//! Props can also carry their own details on the tag (`TagCatch`), so a
+//! particular fish is always "the 23 lb halibut". Anything the tag gives is
+//! used as is, and the rest is generated to fit around it.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::FishInfo;
+use crate::i18n::Language;
+use crate::measurement::{Measurement, Quantity, UnitSystem};
+
+// How many standard deviations of the normal distribution fit in half the
+// species' range, and the furthest out a catch can be (in standard deviations)
+const SIGMAS_PER_HALF_RANGE: f64 = 2.0;
+const MAX_SIGMAS: f64 = 3.0;
+
+// Individual variation on top of the size trend
+const WEIGHT_JITTER: f64 = 0.1;
+const AGE_JITTER: f64 = 0.15;
+
+// "Less than 1 pound" has no lower bound, assume the smallest are about half
+const OPEN_RANGE_MIN_FRACTION: f64 = 0.5;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Catch {
+    pub length: Quantity,
+    pub weight: Quantity,
+    pub age: Quantity,
+    length_text: String,
+    weight_text: String,
+    age_text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nickname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    caught_at: Option<String>,
+}
+
+/// Individual details stored on a prop's tag as a JSON NDEF record, e.g.
+/// `{ "weight": { "value": 23, "unit": "pounds" }, "nickname": "Big Hal" }`.
+/// Every field is optional.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct TagCatch {
+    pub length: Option<Quantity>,
+    pub weight: Option<Quantity>,
+    pub age: Option<Quantity>,
+    pub nickname: Option<String>,
+    /// Where it was "caught", e.g. "Pond 2" or "Baffin Bay"
+    pub caught_at: Option<String>,
+}
+
+pub struct CatchGenerator {
+    rng: StdRng,
+}
+
+impl CatchGenerator {
+    /// Seeded generators give the same sequence of catches every run, for
+    /// tests and demos. Unseeded ones are seeded from the OS.
+    pub fn new(seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self { rng }
+    }
+
+    pub fn generate(
+        &mut self,
+        fish: &FishInfo,
+        tag: Option<&TagCatch>,
+        units: UnitSystem,
+        language: Language,
+    ) -> Catch {
+        let tag = tag.cloned().unwrap_or_default();
+
+        // position within the species' size range, 0.5 is average. Taken from
+        // the tag's length or weight when it has one so the generated values
+        // fit with it
+        let from_length = tag
+            .length
+            .and_then(|length| position_of(&fish.size, length));
+        let from_weight = tag.weight.and_then(|weight| {
+            let (min, max) = bounds(&fish.size);
+            let position = position_of(&fish.weight, weight)?;
+            let length_cubed = lerp_between(min.powi(3), max.powi(3), position);
+            Some((length_cubed.max(0.0).cbrt() - min) / (max - min))
+        });
+        let size = from_length
+            .or(from_weight)
+            .unwrap_or_else(|| 0.5 + self.normal() / (2.0 * SIGMAS_PER_HALF_RANGE));
+
+        let length = tag.length.unwrap_or_else(|| Quantity {
+            value: round_to_tenth(lerp(&fish.size, size)),
+            unit: fish.size.unit,
+        });
+
+        let weight = tag.weight.unwrap_or_else(|| {
+            let (min, max) = bounds(&fish.size);
+            let length = lerp(&fish.size, size);
+            let position = (length.powi(3) - min.powi(3)) / (max.powi(3) - min.powi(3));
+            let weight = lerp(&fish.weight, position) * (1.0 + self.jitter(WEIGHT_JITTER));
+            Quantity {
+                value: round_to_tenth(weight.max(0.1)),
+                unit: fish.weight.unit,
+            }
+        });
+
+        let age = tag.age.unwrap_or_else(|| {
+            let position = size + self.jitter(AGE_JITTER);
+            Quantity {
+                value: lerp(&fish.lifespan, position).round().max(1.0),
+                unit: fish.lifespan.unit,
+            }
+        });
+
+        Catch {
+            length_text: length.format(units, language),
+            weight_text: weight.format(units, language),
+            age_text: age.format(units, language),
+            length,
+            weight,
+            age,
+            nickname: tag.nickname,
+            caught_at: tag.caught_at,
+        }
+    }
+
+    // Standard normal sample (Box-Muller), clamped to MAX_SIGMAS
+    fn normal(&mut self) -> f64 {
+        let u1: f64 = 1.0 - self.rng.gen::<f64>();
+        let u2: f64 = self.rng.gen();
+        let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+        z.clamp(-MAX_SIGMAS, MAX_SIGMAS)
+    }
+
+    fn jitter(&mut self, amount: f64) -> f64 {
+        self.rng.gen_range(-amount..=amount)
+    }
+}
+
+fn bounds(m: &Measurement) -> (f64, f64) {
+    (m.min.unwrap_or(m.max * OPEN_RANGE_MIN_FRACTION), m.max)
+}
+
+// Value at `position` along the range, where 0 is the min and 1 the max.
+// Positions outside 0..1 give catches a bit outside the typical range.
+fn lerp(m: &Measurement, position: f64) -> f64 {
+    let (min, max) = bounds(m);
+    lerp_between(min, max, position)
+}
+
+fn lerp_between(min: f64, max: f64, position: f64) -> f64 {
+    min + (max - min) * position
+}
+
+// Inverse of `lerp`, `None` if the tag's value is in the wrong kind of unit
+fn position_of(m: &Measurement, value: Quantity) -> Option<f64> {
+    let value = value.convert(m.unit)?;
+    let (min, max) = bounds(m);
+    Some((value - min) / (max - min))
+}
+
+fn round_to_tenth(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::Catalog;
+    use crate::measurement::Unit;
+    use std::path::PathBuf;
+
+    fn catches(seed: u64) -> Vec<(f64, f64, f64)> {
+        let catalog = Catalog::bundled(PathBuf::new());
+        let mut generator = CatchGenerator::new(Some(seed));
+        (0..5)
+            .flat_map(|_| catalog.fish())
+            .map(|fish| {
+                let c = generator.generate(fish, None, UnitSystem::Imperial, Language::En);
+                (c.length.value, c.weight.value, c.age.value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_catches() {
+        assert_eq!(catches(42), catches(42));
+        assert_ne!(catches(42), catches(43));
+    }
+
+    #[test]
+    fn catches_are_finite_and_positive() {
+        for (length, weight, age) in catches(7) {
+            assert!(length.is_finite() && weight >= 0.1 && age >= 1.0);
+        }
+    }
+
+    #[test]
+    fn tag_weight_is_kept() {
+        let catalog = Catalog::bundled(PathBuf::new());
+        let tag = TagCatch {
+            weight: Some(Quantity {
+                value: 23.0,
+                unit: Unit::Pounds,
+            }),
+            ..TagCatch::default()
+        };
+        let fish = &catalog.fish()[0];
+        let c = CatchGenerator::new(Some(1)).generate(
+            fish,
+            Some(&tag),
+            UnitSystem::Imperial,
+            Language::En,
+        );
+        assert_eq!(c.weight.value, 23.0);
+        assert!(c.length.value.is_finite());
+    }
+}
@@ -0,0 +1,228 @@
+//! Kiosk configuration, read once at startup from `kiosk.json` in the app's
+//! config directory. Every field has a default so a missing file (or a file
+//! that only sets a few options) is fine.
+
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::i18n::Language;
+use crate::measurement::UnitSystem;
+
+pub const CONFIG_FILE_NAME: &str = "kiosk.json";
+
+const DEFAULT_FISH_URL_PATTERN: &str = "https://sno-port.example/fish/{id}";
+
+/// What `write_fish_tag` puts on a tag
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TagFormat {
+    /// Text record with just the fish id, e.g. "3"
+    #[default]
+    Text,
+    /// URI record from `fish_url_pattern`, phones open it as a web page
+    Url,
+    /// URI record plus the whole fish as a compact record (self-describing),
+    /// for pop-up exhibits. Needs an NTAG215 or NTAG216.
+    Full,
+}
+
+/// What to do with tags that fail the authenticity checks in `signature`
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TagVerification {
+    /// Don't check
+    #[default]
+    Accept,
+    /// Check and log failures, but show the fish anyway
+    Warn,
+    /// Only show fish from tags that pass
+    Reject,
+}
+
+/// What the scanner reads tags with
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ScannerBackend {
+    /// The PN532 NFC HAT, needs the `pn532` feature
+    #[default]
+    Pn532,
+    /// Made-up tags coming and going, see `simulator`
+    Simulator,
+}
+
+/// See `decoder`
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TagDecoderKind {
+    /// NDEF text, URI or self-describing records
+    Ndef,
+    /// Fish id as plain ASCII digits at the start of page 4
+    Ascii,
+    /// Tag UID looked up in the UID registry
+    Uid,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct KioskConfig {
+    /// Overridden by the `--simulator` command line flag
+    pub scanner: ScannerBackend,
+    /// Scenario file for the simulated scanner to play instead of holding
+    /// random tags to the reader, see `scenario`. Overridden by the
+    /// `--scenario` command line flag.
+    pub scenario: Option<PathBuf>,
+    /// File to log the PN532's SPI traffic to, for replaying with
+    /// `--replay`, see `spi`
+    pub spi_capture: Option<PathBuf>,
+    pub unit_system: UnitSystem,
+    pub default_language: Language,
+    /// Show fish in the language code of the tag's NDEF text record when
+    /// it's one the catalog has translations for. "en" doesn't count, the
+    /// kiosk writes it on every tag.
+    pub use_tag_language: bool,
+    /// Catalog file to use instead of the one bundled into the app
+    pub catalog_path: Option<PathBuf>,
+    /// Where catalog-relative paths (images) are looked up, defaults to the
+    /// directory the catalog file is in
+    pub content_dir: Option<PathBuf>,
+    /// Fixed seed for the "your catch" generator, so demos show the same
+    /// catches every run
+    pub catch_seed: Option<u64>,
+    /// URL that fish tags with a URI record point at, `{id}` is replaced by
+    /// the fish id
+    pub fish_url_pattern: String,
+    /// Default format for `write_fish_tag`
+    pub tag_format: TagFormat,
+    /// Ways of recognising a tag to try, in order
+    pub tag_decoders: Vec<TagDecoderKind>,
+    pub tag_verification: TagVerification,
+    /// Key for the HMAC record on tags, see `signature`. When set, tags read
+    /// from NDEF records need a valid HMAC to pass verification, and
+    /// `write_fish_tag` adds one.
+    pub tag_secret: Option<String>,
+    /// Kiosk-wide tag password, 8 hex digits. When set, `write_fish_tag`
+    /// write-protects the tags it writes with it, see `protection`.
+    pub tag_password: Option<String>,
+    /// Also set the lock bits on written tags. Permanent, they can never be
+    /// rewritten afterwards.
+    pub lock_tags: bool,
+}
+
+impl Default for KioskConfig {
+    fn default() -> Self {
+        Self {
+            scanner: ScannerBackend::default(),
+            scenario: None,
+            spi_capture: None,
+            unit_system: UnitSystem::default(),
+            default_language: Language::default(),
+            use_tag_language: true,
+            catalog_path: None,
+            content_dir: None,
+            catch_seed: None,
+            fish_url_pattern: DEFAULT_FISH_URL_PATTERN.to_string(),
+            tag_format: TagFormat::default(),
+            tag_decoders: vec![
+                TagDecoderKind::Ndef,
+                TagDecoderKind::Ascii,
+                TagDecoderKind::Uid,
+            ],
+            tag_verification: TagVerification::default(),
+            tag_secret: None,
+            tag_password: None,
+            lock_tags: false,
+        }
+    }
+}
+
+impl KioskConfig {
+    pub fn load(path: &Path) -> Self {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                println!("Config: No {} found, using defaults", path.display());
+                return Self::default();
+            }
+            Err(e) => {
+                eprintln!("Config: Failed to read {} - {}", path.display(), e);
+                return Self::unreadable();
+            }
+        };
+
+        let mut config: Self = match serde_json::from_str(&contents) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Config: Failed to parse {} - {}", path.display(), e);
+                return Self::unreadable();
+            }
+        };
+
+        if config.fish_url_pattern.matches("{id}").count() != 1 {
+            eprintln!(
+                "Config: fish_url_pattern '{}' needs exactly one {{id}}, using the default",
+                config.fish_url_pattern
+            );
+            config.fish_url_pattern = DEFAULT_FISH_URL_PATTERN.to_string();
+        }
+        if config.tag_password.is_some() && config.tag_password_bytes().is_none() {
+            eprintln!("Config: tag_password needs to be 8 hex digits, tags won't be protected");
+            config.tag_password = None;
+        }
+        config
+    }
+
+    // Defaults, but a typo in a config that turned verification on shouldn't
+    // quietly turn it off again
+    fn unreadable() -> Self {
+        eprintln!("Config: Rejecting unverified tags until the config is fixed");
+        Self {
+            tag_verification: TagVerification::Reject,
+            ..Self::default()
+        }
+    }
+
+    /// The 4 byte NTAG PWD from `tag_password`
+    pub fn tag_password_bytes(&self) -> Option<[u8; 4]> {
+        let hex = self.tag_password.as_deref()?;
+        if hex.len() != 8 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        u32::from_str_radix(hex, 16).ok().map(u32::to_be_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, contents: &str) -> KioskConfig {
+        let path =
+            std::env::temp_dir().join(format!("kiosk-config-{}-{}.json", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let config = KioskConfig::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn missing_file_uses_defaults() {
+        let config = KioskConfig::load(Path::new("/nonexistent/kiosk.json"));
+        assert_eq!(config.tag_verification, TagVerification::Accept);
+    }
+
+    #[test]
+    fn unparseable_file_rejects_unverified_tags() {
+        let config = load("broken", r#"{ "tag_verification": "reject", }"#);
+        assert_eq!(config.tag_verification, TagVerification::Reject);
+        assert_eq!(config.tag_format, TagFormat::Text);
+
+        let config = load("typo", r#"{ "tag_verification": "rejcet" }"#);
+        assert_eq!(config.tag_verification, TagVerification::Reject);
+    }
+
+    #[test]
+    fn parsed_file_keeps_its_setting() {
+        let config = load("warn", r#"{ "tag_verification": "warn" }"#);
+        assert_eq!(config.tag_verification, TagVerification::Warn);
+    }
+}
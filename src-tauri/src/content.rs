@@ -0,0 +1,297 @@
+//! Serves catalog images, video and narration to the webview through the
+//! `fish://` URI scheme, so new media only needs dropping into the content
+//! directory rather than a frontend rebuild.
+//!
+//! Routes:
+//!   fish://localhost/images/<id>           the main image for fish `<id>`
+//!   fish://localhost/media/<id>/<index>    entry `<index>` of its media list
+
+use std::path::{Component, Path, PathBuf};
+use tauri::http::{header, Request, Response, StatusCode};
+
+use crate::catalog::{Catalog, FishInfo, MediaKind};
+
+pub const SCHEME: &str = "fish";
+
+const PLACEHOLDER_IMAGE: &[u8] = include_bytes!("../catalog/placeholder.svg");
+
+// Windows and Android webviews can't load custom schemes directly, Tauri
+// exposes them as http(s)://<scheme>.localhost instead (https since the
+// window sets useHttpsScheme)
+#[cfg(any(windows, target_os = "android"))]
+const URL_BASE: &str = "https://fish.localhost";
+#[cfg(not(any(windows, target_os = "android")))]
+const URL_BASE: &str = "fish://localhost";
+
+pub fn image_url(fish_id: u32) -> String {
+    format!("{}/images/{}", URL_BASE, fish_id)
+}
+
+pub fn media_url(fish_id: u32, index: usize) -> String {
+    format!("{}/media/{}/{}", URL_BASE, fish_id, index)
+}
+
+pub fn handle_request(catalog: &Catalog, request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let path = request.uri().path().trim_matches('/');
+    let mut segments = path.split('/');
+
+    let route = segments.next();
+    let fish = segments
+        .next()
+        .and_then(|id| id.parse().ok())
+        .and_then(|id| catalog.get(id));
+    let Some(fish) = fish else {
+        // fish shown from a self-describing tag aren't in the catalog, they
+        // still get an image
+        return match route {
+            Some("images") => placeholder_response(request),
+            _ => status(StatusCode::NOT_FOUND),
+        };
+    };
+
+    let (file, is_image) = match (route, segments.next()) {
+        (Some("images"), None) => (fish.image_path.as_str(), true),
+        (Some("media"), Some(index)) => {
+            let Some(media) = index.parse().ok().and_then(|i: usize| fish.media.get(i)) else {
+                return status(StatusCode::NOT_FOUND);
+            };
+            (media.path.as_str(), media.kind == MediaKind::Image)
+        }
+        _ => return status(StatusCode::NOT_FOUND),
+    };
+
+    match read_file(catalog.content_dir(), fish, file) {
+        Some(bytes) => file_response(request, file, bytes),
+        None if is_image => placeholder_response(request),
+        None => status(StatusCode::NOT_FOUND),
+    }
+}
+
+fn read_file(content_dir: &Path, fish: &FishInfo, file: &str) -> Option<Vec<u8>> {
+    let Some(path) = resolve(content_dir, file) else {
+        eprintln!("Content: Fish ID '{}' - Invalid path {}", fish.id, file);
+        return None;
+    };
+
+    match std::fs::read(&path) {
+        Ok(bytes) => Some(bytes),
+        Err(e) => {
+            eprintln!(
+                "Content: Fish ID '{}' - {} - {}",
+                fish.id,
+                path.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Joins a catalog-relative path onto the content directory, refusing
+/// anything that could escape it (absolute paths, `..`).
+pub fn resolve(content_dir: &Path, relative: &str) -> Option<PathBuf> {
+    // backslashes and drive letters only mean something on Windows, and
+    // percent-encoded separators only once something decodes them, but a
+    // catalog written on one machine shouldn't behave differently on another
+    if relative.contains(['\\', ':', '%']) {
+        return None;
+    }
+
+    let relative = Path::new(relative);
+    let mut components = relative.components().peekable();
+    if components.peek().is_none() || !components.all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    Some(content_dir.join(relative))
+}
+
+pub fn mime_type(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    match extension.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "svg" => "image/svg+xml",
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "ogg" | "oga" => "audio/ogg",
+        "wav" => "audio/wav",
+        _ => "application/octet-stream",
+    }
+}
+
+// Webviews fetch video and audio with Range requests and won't play (or
+// can't seek/loop) without a 206 response, so honour a single byte range
+fn file_response(request: &Request<Vec<u8>>, path: &str, bytes: Vec<u8>) -> Response<Vec<u8>> {
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, mime_type(path))
+        .header(header::ACCEPT_RANGES, "bytes");
+
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| parse_range(value, bytes.len()));
+
+    match range {
+        None => builder.body(bytes).unwrap(),
+        Some(Some((start, end))) => builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, bytes.len()),
+            )
+            .body(bytes[start..=end].to_vec())
+            .unwrap(),
+        Some(None) => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", bytes.len()))
+            .body(Vec::new())
+            .unwrap(),
+    }
+}
+
+fn placeholder_response(request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+    file_response(request, "placeholder.svg", PLACEHOLDER_IMAGE.to_vec())
+}
+
+// "bytes=start-end", "bytes=start-" or "bytes=-suffix" to an inclusive range
+fn parse_range(value: &str, len: usize) -> Option<(usize, usize)> {
+    let (start, end) = value.strip_prefix("bytes=")?.split_once('-')?;
+    let last = len.checked_sub(1)?;
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: usize = suffix.parse().ok()?;
+            (len.checked_sub(suffix.min(len))?, last)
+        }
+        (start, "") => (start.parse().ok()?, last),
+        (start, end) => (start.parse().ok()?, end.parse::<usize>().ok()?.min(last)),
+    };
+
+    (start <= end).then_some((start, end))
+}
+
+fn status(status: StatusCode) -> Response<Vec<u8>> {
+    Response::builder().status(status).body(Vec::new()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_under_the_content_dir() {
+        let dir = Path::new("/srv/catalog");
+        assert_eq!(
+            resolve(dir, "images/3.png"),
+            Some(PathBuf::from("/srv/catalog/images/3.png"))
+        );
+        assert_eq!(
+            resolve(dir, "salmon.jpg"),
+            Some(PathBuf::from("/srv/catalog/salmon.jpg"))
+        );
+    }
+
+    #[test]
+    fn refuses_paths_out_of_the_content_dir() {
+        let dir = Path::new("/srv/catalog");
+        let escapes = [
+            "",
+            ".",
+            "..",
+            "../kiosk.json",
+            "images/../../kiosk.json",
+            "./images/3.png",
+            "/etc/passwd",
+            "C:",
+            "C:/Windows/win.ini",
+            "C:\\Windows\\win.ini",
+            "\\\\server\\share\\3.png",
+            "images\\..\\..\\kiosk.json",
+            "..%2Fkiosk.json",
+            "images%2F..%2F..%2Fkiosk.json",
+            "%2e%2e/kiosk.json",
+            "images%5C3.png",
+        ];
+        for path in escapes {
+            assert_eq!(resolve(dir, path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("bytes=0-", 100), Some((0, 99)));
+        assert_eq!(parse_range("bytes=10-19", 100), Some((10, 19)));
+        assert_eq!(parse_range("bytes=99-99", 100), Some((99, 99)));
+        // the last N bytes, or all of them if there are fewer
+        assert_eq!(parse_range("bytes=-10", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=-500", 100), Some((0, 99)));
+        // an end past the end is cut short
+        assert_eq!(parse_range("bytes=50-500", 100), Some((50, 99)));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=100-", 100), None);
+        assert_eq!(parse_range("bytes=150-200", 100), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+        assert_eq!(parse_range("bytes=-0", 100), None);
+    }
+
+    #[test]
+    fn malformed_ranges() {
+        let malformed = [
+            "",
+            "bytes=",
+            "bytes=-",
+            "bytes=5",
+            "bytes=abc-",
+            "bytes=1-x",
+            "bytes=20-10",
+            "bytes=0-1,5-6",
+            "items=0-10",
+            "bytes 0-10",
+        ];
+        for value in malformed {
+            assert_eq!(parse_range(value, 100), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn range_responses() {
+        let request = |range: Option<&str>| {
+            let mut builder = Request::builder().uri("fish://localhost/media/1/0");
+            if let Some(range) = range {
+                builder = builder.header(header::RANGE, range);
+            }
+            builder.body(Vec::new()).unwrap()
+        };
+        let bytes: Vec<u8> = (0..100).collect();
+        let respond = |range| file_response(&request(range), "loop.mp4", bytes.clone());
+
+        let whole = respond(None);
+        assert_eq!(whole.status(), StatusCode::OK);
+        assert_eq!(whole.headers()[header::CONTENT_TYPE], "video/mp4");
+        assert_eq!(whole.body().len(), 100);
+
+        let partial = respond(Some("bytes=90-"));
+        assert_eq!(partial.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(partial.headers()[header::CONTENT_RANGE], "bytes 90-99/100");
+        assert_eq!(partial.body(), &bytes[90..]);
+
+        let past_the_end = respond(Some("bytes=100-"));
+        assert_eq!(past_the_end.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(past_the_end.headers()[header::CONTENT_RANGE], "bytes */100");
+        assert!(past_the_end.body().is_empty());
+    }
+}
@@ -0,0 +1,290 @@
+//! Ways of working out which fish a tag is. Props across exhibits are
+//! encoded differently (NDEF records, raw ASCII, or nothing but their UID),
+//! so the scanner tries each decoder in the configured order until one
+//! recognises the tag. Decoders only see what was read off the tag, so they
+//! work the same on captured dumps as on live hardware.
+
+use std::sync::{Arc, Mutex};
+
+use crate::config::{KioskConfig, TagDecoderKind};
+use crate::ndef;
+use crate::tag::{self, FishTag};
+use crate::uid_registry::UidRegistry;
+
+/// NTAG21x variants, told apart by the user memory size in the capability
+/// container
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagType {
+    Ntag213,
+    Ntag215,
+    Ntag216,
+    Unknown,
+}
+
+impl TagType {
+    pub fn from_memory(memory: &[u8]) -> Self {
+        ndef::data_area_size(memory).map_or(TagType::Unknown, Self::from_data_area_size)
+    }
+
+    pub fn from_data_area_size(size: usize) -> Self {
+        match size {
+            144 => TagType::Ntag213,
+            496 => TagType::Ntag215,
+            872 => TagType::Ntag216,
+            _ => TagType::Unknown,
+        }
+    }
+
+    /// CFG0, the first of the configuration pages (CFG0, CFG1, PWD, PACK)
+    pub fn config_page(self) -> Option<u8> {
+        match self {
+            TagType::Ntag213 => Some(0x29),
+            TagType::Ntag215 => Some(0x83),
+            TagType::Ntag216 => Some(0xE3),
+            TagType::Unknown => None,
+        }
+    }
+}
+
+/// Everything read off a tag
+#[derive(Debug, Clone, Copy)]
+pub struct TagRead<'a> {
+    pub uid: &'a [u8],
+    pub tag_type: TagType,
+    /// Memory dump starting at page 0
+    pub memory: &'a [u8],
+}
+
+impl<'a> TagRead<'a> {
+    pub fn new(uid: &'a [u8], memory: &'a [u8]) -> Self {
+        Self {
+            uid,
+            tag_type: TagType::from_memory(memory),
+            memory,
+        }
+    }
+}
+
+pub trait TagDecoder: Send {
+    fn name(&self) -> &'static str;
+
+    /// The fish on the tag, or `None` if it isn't in this decoder's format
+    fn decode(&self, tag: &TagRead) -> Option<FishTag>;
+}
+
+/// NDEF text, URI and self-describing records, see `tag::FishTag::from_ndef`
+pub struct NdefDecoder {
+    pub url_pattern: String,
+}
+
+impl TagDecoder for NdefDecoder {
+    fn name(&self) -> &'static str {
+        "ndef"
+    }
+
+    fn decode(&self, tag: &TagRead) -> Option<FishTag> {
+        FishTag::from_ndef(tag.memory, &self.url_pattern)
+    }
+}
+
+/// The fish id written as plain ASCII digits at the start of page 4, with no
+/// NDEF formatting, e.g. "12" followed by zeros
+pub struct AsciiDecoder;
+
+impl TagDecoder for AsciiDecoder {
+    fn name(&self) -> &'static str {
+        "ascii"
+    }
+
+    fn decode(&self, tag: &TagRead) -> Option<FishTag> {
+        let page4 = tag.memory.get(ndef::USER_MEMORY_OFFSET..)?;
+        tag::parse_digits(page4).map(FishTag::from_id)
+    }
+}
+
+/// Looks the UID up in the UID registry
+pub struct UidDecoder {
+    pub registry: Arc<Mutex<UidRegistry>>,
+}
+
+impl TagDecoder for UidDecoder {
+    fn name(&self) -> &'static str {
+        "uid"
+    }
+
+    fn decode(&self, tag: &TagRead) -> Option<FishTag> {
+        let fish_id = self.registry.lock().unwrap().get(tag.uid)?;
+        Some(FishTag::from_id(fish_id))
+    }
+}
+
+/// The decoders listed in the config, in order
+pub fn from_config(
+    config: &KioskConfig,
+    registry: Arc<Mutex<UidRegistry>>,
+) -> Vec<Box<dyn TagDecoder>> {
+    config
+        .tag_decoders
+        .iter()
+        .map(|kind| -> Box<dyn TagDecoder> {
+            match kind {
+                TagDecoderKind::Ndef => Box::new(NdefDecoder {
+                    url_pattern: config.fish_url_pattern.clone(),
+                }),
+                TagDecoderKind::Ascii => Box::new(AsciiDecoder),
+                TagDecoderKind::Uid => Box::new(UidDecoder {
+                    registry: registry.clone(),
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Runs `decoders` in order and returns the first fish found, along with the
+/// name of the decoder that found it
+pub fn decode(decoders: &[Box<dyn TagDecoder>], tag: &TagRead) -> Option<(FishTag, &'static str)> {
+    decoders
+        .iter()
+        .find_map(|decoder| Some((decoder.decode(tag)?, decoder.name())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NTAG213 with UID 04 A1 B2 C3 D4 E5 F6, the pages before user memory
+    const HEADER: &str = "
+        04 A1 B2 9F
+        C3 D4 E5 F6
+        04 48 00 00
+        E1 10 12 00";
+    const UID: [u8; 7] = [0x04, 0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6];
+
+    // text record "3", language "en"
+    const NDEF_TEXT: &str = "
+        03 08 D1 01
+        04 54 02 65
+        6E 33 FE 00";
+    // URI record "https://sno-port.example/fish/5"
+    const NDEF_URI: &str = "
+        03 1C D1 01
+        18 55 04 73
+        6E 6F 2D 70
+        6F 72 74 2E
+        65 78 61 6D
+        70 6C 65 2F
+        66 69 73 68
+        2F 35 FE 00";
+    // text record "7" claiming more than was read, the pages after it failed
+    const LEGACY_CUT_SHORT: &str = "
+        03 20 D1 01
+        1C 54 02 65
+        6E 37 00 00";
+    // "12" with no NDEF formatting
+    const RAW_ASCII: &str = "
+        31 32 00 00
+        00 00 00 00";
+    // empty NDEF message, as tags come from the factory
+    const BLANK: &str = "
+        03 00 FE 00
+        00 00 00 00";
+
+    fn dump(user_memory: &str) -> Vec<u8> {
+        format!("{} {}", HEADER, user_memory)
+            .split_whitespace()
+            .map(|byte| u8::from_str_radix(byte, 16).unwrap())
+            .collect()
+    }
+
+    // registry with UID enrolled as fish 9, from a file like the kiosk's
+    fn registry(name: &str) -> Arc<Mutex<UidRegistry>> {
+        let dir = std::env::temp_dir().join(format!("decoder-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tag_uids.json");
+        std::fs::write(&path, r#"{ "04:a1:b2:c3:d4:e5:f6": 9 }"#).unwrap();
+        let registry = UidRegistry::load(&path);
+        std::fs::remove_dir_all(&dir).ok();
+        Arc::new(Mutex::new(registry))
+    }
+
+    fn decoders(
+        kinds: &[TagDecoderKind],
+        registry: Arc<Mutex<UidRegistry>>,
+    ) -> Vec<Box<dyn TagDecoder>> {
+        let config = KioskConfig {
+            tag_decoders: kinds.to_vec(),
+            ..KioskConfig::default()
+        };
+        from_config(&config, registry)
+    }
+
+    fn decode_dump(
+        decoders: &[Box<dyn TagDecoder>],
+        user_memory: &str,
+    ) -> Option<(u32, &'static str)> {
+        let memory = dump(user_memory);
+        let (tag, name) = decode(decoders, &TagRead::new(&UID, &memory))?;
+        Some((tag.fish_id, name))
+    }
+
+    #[test]
+    fn tag_type_from_capability_container() {
+        let memory = dump(BLANK);
+        let read = TagRead::new(&UID, &memory);
+        assert_eq!(read.tag_type, TagType::Ntag213);
+        assert_eq!(read.tag_type.config_page(), Some(0x29));
+    }
+
+    #[test]
+    fn ndef_text_and_uri() {
+        let decoders = decoders(&[TagDecoderKind::Ndef], registry("ndef"));
+        let memory = dump(NDEF_TEXT);
+        let (tag, _) = decode(&decoders, &TagRead::new(&UID, &memory)).unwrap();
+        assert_eq!((tag.fish_id, tag.language.as_deref()), (3, Some("en")));
+        assert_eq!(decode_dump(&decoders, NDEF_URI), Some((5, "ndef")));
+    }
+
+    #[test]
+    fn legacy_en_digits() {
+        let decoders = decoders(&[TagDecoderKind::Ndef], registry("legacy"));
+        assert_eq!(decode_dump(&decoders, LEGACY_CUT_SHORT), Some((7, "ndef")));
+    }
+
+    #[test]
+    fn raw_ascii_digits() {
+        let decoders = decoders(
+            &[TagDecoderKind::Ndef, TagDecoderKind::Ascii],
+            registry("ascii"),
+        );
+        assert_eq!(decode_dump(&decoders, RAW_ASCII), Some((12, "ascii")));
+        assert_eq!(decode_dump(&decoders, NDEF_TEXT), Some((3, "ndef")));
+    }
+
+    #[test]
+    fn blank_tag_by_uid() {
+        let default_order = KioskConfig::default().tag_decoders;
+        let with_uid = decoders(&default_order, registry("blank"));
+        assert_eq!(decode_dump(&with_uid, BLANK), Some((9, "uid")));
+
+        let without_uid = decoders(
+            &[TagDecoderKind::Ndef, TagDecoderKind::Ascii],
+            registry("blank2"),
+        );
+        assert_eq!(decode_dump(&without_uid, BLANK), None);
+    }
+
+    #[test]
+    fn decoders_run_in_configured_order() {
+        let ndef_first = decoders(
+            &[TagDecoderKind::Ndef, TagDecoderKind::Uid],
+            registry("order1"),
+        );
+        assert_eq!(decode_dump(&ndef_first, NDEF_TEXT), Some((3, "ndef")));
+
+        let uid_first = decoders(
+            &[TagDecoderKind::Uid, TagDecoderKind::Ndef],
+            registry("order2"),
+        );
+        assert_eq!(decode_dump(&uid_first, NDEF_TEXT), Some((9, "uid")));
+    }
+}
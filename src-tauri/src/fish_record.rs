@@ -0,0 +1,424 @@
+//! Compact binary fish records, for self-describing tags that carry a whole
+//! fish (name, facts, status) so a kiosk can show fish that aren't in its
+//! catalog. Meant for NTAG215/216, with translations left out as needed to
+//! fit (see `tag::encode_fish_tag`).
+//!
+//! Version 1 layout:
+//!
+//!   version   u8, currently 1
+//!   fish id   varint (LEB128)
+//!   fields    repeated until the end of the record:
+//!               key     u8, field << 2 | language (0 = en, 1 = fr, 2 = es)
+//!               length  varint
+//!               value   UTF-8 text, or for measurements
+//!                       unit u8, flags u8 (bit 0 = has min), [min f32], max f32
+//!
+//! Numbers are little-endian. Unknown fields are skipped, so fields can be
+//! added without breaking older kiosks, anything incompatible bumps the
+//! version instead.
+
+use crate::catalog::FishInfo;
+use crate::i18n::{Language, Localized};
+use crate::measurement::{Measurement, Unit};
+
+pub const VERSION: u8 = 1;
+
+const FIELD_NAME: u8 = 1;
+const FIELD_SCIENTIFIC_NAME: u8 = 2;
+const FIELD_FAMILY: u8 = 3;
+const FIELD_ORDER: u8 = 4;
+const FIELD_SIZE: u8 = 5;
+const FIELD_WEIGHT: u8 = 6;
+const FIELD_LIFESPAN: u8 = 7;
+const FIELD_HABITAT: u8 = 8;
+const FIELD_DIET: u8 = 9;
+const FIELD_ENDANGERED_STATUS: u8 = 10;
+const FIELD_BLURB: u8 = 11;
+const FIELD_FUN_FACT: u8 = 12;
+const FIELD_COUNT: usize = 13;
+
+const FLAG_HAS_MIN: u8 = 0x01;
+
+/// Encodes `fish` with its text in `languages` only, and the blurb (by far
+/// the longest text) only if `blurb` is set. English is always included
+/// since it's what the other languages fall back to.
+pub fn encode(fish: &FishInfo, languages: &[Language], blurb: bool) -> Vec<u8> {
+    let mut out = vec![VERSION];
+    write_varint(&mut out, fish.id);
+
+    let plain = |text: &str| Localized {
+        en: text.to_string(),
+        ..empty_text()
+    };
+    let text_fields = [
+        (FIELD_NAME, &fish.name),
+        (FIELD_SCIENTIFIC_NAME, &plain(&fish.scientific_name)),
+        (FIELD_FAMILY, &plain(&fish.family)),
+        (FIELD_ORDER, &plain(&fish.order)),
+        (FIELD_HABITAT, &fish.habitat),
+        (FIELD_DIET, &fish.diet),
+        (FIELD_ENDANGERED_STATUS, &fish.endangered_status),
+        (FIELD_BLURB, &fish.blurb),
+        (FIELD_FUN_FACT, &fish.fun_fact),
+    ];
+    for (field, text) in text_fields {
+        if field == FIELD_BLURB && !blurb {
+            continue;
+        }
+        let translations = [
+            (Language::En, Some(&text.en)),
+            (Language::Fr, text.fr.as_ref()),
+            (Language::Es, text.es.as_ref()),
+        ];
+        for (code, (language, value)) in translations.into_iter().enumerate() {
+            if language != Language::En && !languages.contains(&language) {
+                continue;
+            }
+            match value {
+                Some(value) if !value.is_empty() => {
+                    write_field(&mut out, field << 2 | code as u8, value.as_bytes())
+                }
+                _ => {}
+            }
+        }
+    }
+
+    for (field, m) in [
+        (FIELD_SIZE, &fish.size),
+        (FIELD_WEIGHT, &fish.weight),
+        (FIELD_LIFESPAN, &fish.lifespan),
+    ] {
+        let mut value = vec![unit_code(m.unit)];
+        match m.min {
+            Some(min) => {
+                value.push(FLAG_HAS_MIN);
+                value.extend_from_slice(&(min as f32).to_le_bytes());
+            }
+            None => value.push(0),
+        }
+        value.extend_from_slice(&(m.max as f32).to_le_bytes());
+        write_field(&mut out, field << 2, &value);
+    }
+
+    out
+}
+
+/// Decodes a record written by `encode`. Only the name and the size, weight
+/// and lifespan are required, missing text is left empty.
+pub fn decode(data: &[u8]) -> Result<FishInfo, String> {
+    let mut reader = Reader { data, pos: 0 };
+
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(format!("Unsupported fish record version {}", version));
+    }
+    let id = reader.varint()?;
+
+    let mut texts: [Localized; FIELD_COUNT] = std::array::from_fn(|_| empty_text());
+    let mut measurements: [Option<Measurement>; FIELD_COUNT] = [None; FIELD_COUNT];
+
+    while reader.pos < data.len() {
+        let key = reader.byte()?;
+        let len = reader.varint()? as usize;
+        let value = reader.take(len)?;
+        let (field, language) = ((key >> 2) as usize, key & 0x03);
+
+        match field as u8 {
+            FIELD_SIZE | FIELD_WEIGHT | FIELD_LIFESPAN => {
+                measurements[field] = Some(decode_measurement(value)?);
+            }
+            FIELD_NAME..=FIELD_FUN_FACT => {
+                let text = String::from_utf8(value.to_vec())
+                    .map_err(|_| format!("Field {} isn't valid UTF-8", field))?;
+                match language {
+                    0 => texts[field].en = text,
+                    1 => texts[field].fr = Some(text),
+                    2 => texts[field].es = Some(text),
+                    _ => {}
+                }
+            }
+            // written by something newer, skip it
+            _ => {}
+        }
+    }
+
+    let mut take_text = |field: u8| std::mem::replace(&mut texts[field as usize], empty_text());
+    let name = take_text(FIELD_NAME);
+    if name.en.is_empty() {
+        return Err("Missing name".to_string());
+    }
+    let measurement = |field: u8, what: &str| {
+        measurements[field as usize].ok_or_else(|| format!("Missing {}", what))
+    };
+
+    Ok(FishInfo {
+        id,
+        name,
+        scientific_name: take_text(FIELD_SCIENTIFIC_NAME).en,
+        family: take_text(FIELD_FAMILY).en,
+        order: take_text(FIELD_ORDER).en,
+        size: measurement(FIELD_SIZE, "size")?,
+        weight: measurement(FIELD_WEIGHT, "weight")?,
+        lifespan: measurement(FIELD_LIFESPAN, "lifespan")?,
+        habitat: take_text(FIELD_HABITAT),
+        habitat_range: None,
+        depth_range: None,
+        diet: take_text(FIELD_DIET),
+        endangered_status: take_text(FIELD_ENDANGERED_STATUS),
+        blurb: take_text(FIELD_BLURB),
+        image_path: String::new(),
+        fun_fact: take_text(FIELD_FUN_FACT),
+        media: Vec::new(),
+        related: Vec::new(),
+        prey: Vec::new(),
+    })
+}
+
+fn empty_text() -> Localized {
+    Localized {
+        en: String::new(),
+        fr: None,
+        es: None,
+    }
+}
+
+fn decode_measurement(value: &[u8]) -> Result<Measurement, String> {
+    let mut reader = Reader {
+        data: value,
+        pos: 0,
+    };
+    let unit = unit_from_code(reader.byte()?)?;
+    let flags = reader.byte()?;
+    let min = if flags & FLAG_HAS_MIN != 0 {
+        Some(reader.f32()? as f64)
+    } else {
+        None
+    };
+    let max = reader.f32()? as f64;
+
+    let measurement = Measurement { min, max, unit };
+    measurement
+        .validate()
+        .map_err(|e| format!("Invalid measurement range, {}", e))?;
+    Ok(measurement)
+}
+
+fn write_field(out: &mut Vec<u8>, key: u8, value: &[u8]) {
+    out.push(key);
+    write_varint(out, value.len() as u32);
+    out.extend_from_slice(value);
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len());
+        let Some(end) = end else {
+            return Err("Record is truncated".to_string());
+        };
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        let bytes = self.take(4)?;
+        Ok(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // at most 5 bytes for a u32, the last holding only the top 4 bits
+    fn varint(&mut self) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..5 {
+            let byte = self.byte()?;
+            if i == 4 && byte > 0x0F {
+                return Err("Varint overflows".to_string());
+            }
+            value |= ((byte & 0x7F) as u32) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Varint overflows".to_string())
+    }
+}
+
+fn unit_code(unit: Unit) -> u8 {
+    match unit {
+        Unit::Inches => 0,
+        Unit::Feet => 1,
+        Unit::Pounds => 2,
+        Unit::Centimetres => 3,
+        Unit::Metres => 4,
+        Unit::Kilograms => 5,
+        Unit::Years => 6,
+    }
+}
+
+fn unit_from_code(code: u8) -> Result<Unit, String> {
+    match code {
+        0 => Ok(Unit::Inches),
+        1 => Ok(Unit::Feet),
+        2 => Ok(Unit::Pounds),
+        3 => Ok(Unit::Centimetres),
+        4 => Ok(Unit::Metres),
+        5 => Ok(Unit::Kilograms),
+        6 => Ok(Unit::Years),
+        _ => Err(format!("Unknown unit {}", code)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::Catalog;
+    use std::path::PathBuf;
+
+    #[test]
+    fn round_trip() {
+        let catalog = Catalog::bundled(PathBuf::new());
+        for fish in catalog.fish() {
+            let decoded = decode(&encode(fish, &[Language::En], false)).unwrap();
+            assert_eq!(decoded.id, fish.id);
+            assert_eq!(decoded.size, fish.size);
+            assert_eq!(decoded.lifespan, fish.lifespan);
+        }
+    }
+
+    fn text(text: &Localized) -> (&str, Option<&str>, Option<&str>) {
+        (&text.en, text.fr.as_deref(), text.es.as_deref())
+    }
+
+    // measurements go on the tag as f32
+    fn as_f32(m: Measurement) -> Measurement {
+        Measurement {
+            min: m.min.map(|min| min as f32 as f64),
+            max: m.max as f32 as f64,
+            unit: m.unit,
+        }
+    }
+
+    #[test]
+    fn round_trip_every_field() {
+        let catalog = Catalog::bundled(PathBuf::new());
+        for fish in catalog.fish() {
+            let decoded = decode(&encode(fish, &[Language::Fr, Language::Es], true)).unwrap();
+            assert_eq!(decoded.id, fish.id);
+            assert_eq!(text(&decoded.name), text(&fish.name));
+            assert_eq!(decoded.scientific_name, fish.scientific_name);
+            assert_eq!(decoded.family, fish.family);
+            assert_eq!(decoded.order, fish.order);
+            assert_eq!(text(&decoded.habitat), text(&fish.habitat));
+            assert_eq!(text(&decoded.diet), text(&fish.diet));
+            assert_eq!(
+                text(&decoded.endangered_status),
+                text(&fish.endangered_status)
+            );
+            assert_eq!(text(&decoded.blurb), text(&fish.blurb));
+            assert_eq!(text(&decoded.fun_fact), text(&fish.fun_fact));
+            assert_eq!(decoded.size, as_f32(fish.size));
+            assert_eq!(decoded.weight, as_f32(fish.weight));
+            assert_eq!(decoded.lifespan, as_f32(fish.lifespan));
+        }
+    }
+
+    #[test]
+    fn translations_and_blurb_left_out() {
+        let catalog = Catalog::bundled(PathBuf::new());
+        let fish = &catalog.fish()[0];
+
+        let english = decode(&encode(fish, &[], false)).unwrap();
+        assert_eq!(text(&english.name), (fish.name.en.as_str(), None, None));
+        assert_eq!(text(&english.blurb), ("", None, None));
+
+        let french = decode(&encode(fish, &[Language::Fr], true)).unwrap();
+        assert_eq!(
+            text(&french.fun_fact),
+            (fish.fun_fact.en.as_str(), fish.fun_fact.fr.as_deref(), None)
+        );
+        assert_eq!(text(&french.blurb).1, fish.blurb.fr.as_deref());
+    }
+
+    #[test]
+    fn truncated_records_are_rejected() {
+        let catalog = Catalog::bundled(PathBuf::new());
+        let fish = &catalog.fish()[0];
+        let record = encode(fish, &[Language::Fr], false);
+        // the measurements come last, so every cut loses one
+        for len in 0..record.len() {
+            assert!(decode(&record[..len]).is_err(), "cut at {}", len);
+        }
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let catalog = Catalog::bundled(PathBuf::new());
+        let fish = &catalog.fish()[0];
+        let mut record = encode(fish, &[], false);
+        record[0] = VERSION + 1;
+        assert_eq!(
+            decode(&record).unwrap_err(),
+            "Unsupported fish record version 2"
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_skipped() {
+        let catalog = Catalog::bundled(PathBuf::new());
+        let fish = &catalog.fish()[0];
+        let mut record = encode(fish, &[], false);
+        write_field(&mut record, (FIELD_COUNT as u8) << 2, b"from a newer kiosk");
+        assert_eq!(decode(&record).unwrap().id, fish.id);
+    }
+
+    #[test]
+    fn fits_an_ntag215() {
+        const NTAG215_DATA_AREA: usize = 496;
+        let catalog = Catalog::bundled(PathBuf::new());
+        for fish in catalog.fish() {
+            let user_memory = crate::tag::encode_fish_tag(
+                fish,
+                crate::config::TagFormat::Full,
+                "https://sno-port.example/fish/{id}",
+                None,
+                NTAG215_DATA_AREA,
+            )
+            .unwrap();
+            assert!(user_memory.len() <= NTAG215_DATA_AREA, "fish {}", fish.id);
+
+            // whatever had to be left out, what's there still reads back
+            let mut memory = vec![0; crate::ndef::USER_MEMORY_OFFSET];
+            memory.extend(user_memory);
+            let records = crate::ndef::parse_tag_memory(&memory).unwrap();
+            let decoded = decode(&records[1].payload).unwrap();
+            assert_eq!(text(&decoded.name).0, fish.name.en);
+        }
+    }
+
+    #[test]
+    fn degenerate_range_is_rejected() {
+        let mut fish = Catalog::bundled(PathBuf::new()).fish()[0].clone();
+        fish.weight.min = Some(fish.weight.max);
+        let err = decode(&encode(&fish, &[Language::En], false)).unwrap_err();
+        assert!(err.starts_with("Invalid measurement range"), "{}", err);
+    }
+}
@@ -0,0 +1,324 @@
+//! Predator/prey relationships between catalog fish and the other organisms
+//! (krill, seals, seabirds...) they eat or get eaten by.
+//!
+//! Every node lists what it eats in `prey`, as fish ids (numbers) or organism
+//! ids (strings). Predators are worked out from those lists, so each link is
+//! only written down once.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt;
+
+use crate::catalog::Catalog;
+use crate::i18n::{Language, Localized};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(untagged)]
+pub enum NodeRef {
+    Fish(u32),
+    Organism(String),
+}
+
+impl fmt::Display for NodeRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeRef::Fish(id) => write!(f, "fish:{}", id),
+            NodeRef::Organism(id) => write!(f, "organism:{}", id),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrganismKind {
+    Plankton,
+    Invertebrate,
+    Fish,
+    Bird,
+    Mammal,
+}
+
+/// Anything in the food web that isn't a catalog fish
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Organism {
+    pub id: String,
+    pub name: Localized,
+    #[serde(default)]
+    pub scientific_name: Option<String>,
+    pub kind: OrganismKind,
+    #[serde(default)]
+    pub prey: Vec<NodeRef>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FoodWebNode {
+    /// "fish:<id>" or "organism:<id>", what edges refer to
+    id: String,
+    fish_id: Option<u32>,
+    name: String,
+    kind: OrganismKind,
+    /// Hops from the fish the web was built around
+    distance: u32,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FoodWebEdge {
+    predator: String,
+    prey: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FoodWeb {
+    nodes: Vec<FoodWebNode>,
+    edges: Vec<FoodWebEdge>,
+}
+
+/// Every (predator, prey) link in the catalog
+fn links(catalog: &Catalog) -> Vec<(NodeRef, &NodeRef)> {
+    let fish = catalog
+        .fish()
+        .iter()
+        .flat_map(|f| f.prey.iter().map(|prey| (NodeRef::Fish(f.id), prey)));
+    let organisms = catalog.organisms().iter().flat_map(|o| {
+        o.prey
+            .iter()
+            .map(|prey| (NodeRef::Organism(o.id.clone()), prey))
+    });
+    fish.chain(organisms).collect()
+}
+
+/// Checks every prey reference points at something in the catalog
+pub fn validate(catalog: &Catalog) -> Result<(), String> {
+    for (i, organism) in catalog.organisms().iter().enumerate() {
+        if catalog.organisms()[..i].iter().any(|o| o.id == organism.id) {
+            return Err(format!("Organism '{}' - Duplicate id", organism.id));
+        }
+    }
+
+    for (predator, prey) in links(catalog) {
+        if !exists(catalog, prey) {
+            return Err(format!("{} - Prey '{}' not found", predator, prey));
+        }
+    }
+    Ok(())
+}
+
+fn exists(catalog: &Catalog, node: &NodeRef) -> bool {
+    match node {
+        NodeRef::Fish(id) => catalog.get(*id).is_some(),
+        NodeRef::Organism(id) => catalog.organism(id).is_some(),
+    }
+}
+
+/// The part of the food web within `depth` predator/prey links of fish
+/// `fish_id`, or `None` if there's no such fish.
+pub fn neighbourhood(
+    catalog: &Catalog,
+    fish_id: u32,
+    depth: u32,
+    language: Language,
+) -> Option<FoodWeb> {
+    catalog.get(fish_id)?;
+    let links = links(catalog);
+
+    // breadth first over links in either direction, so both what the fish
+    // eats and what eats it are one hop away
+    let start = NodeRef::Fish(fish_id);
+    let mut distances = vec![(start.clone(), 0)];
+    let mut queue = VecDeque::from([(start, 0)]);
+
+    while let Some((node, distance)) = queue.pop_front() {
+        if distance == depth {
+            continue;
+        }
+
+        for (predator, prey) in &links {
+            let next = if predator == &node {
+                *prey
+            } else if *prey == &node {
+                predator
+            } else {
+                continue;
+            };
+
+            if !distances.iter().any(|(n, _)| n == next) {
+                distances.push((next.clone(), distance + 1));
+                queue.push_back((next.clone(), distance + 1));
+            }
+        }
+    }
+
+    let included = |node: &NodeRef| distances.iter().any(|(n, _)| n == node);
+    let edges: BTreeSet<FoodWebEdge> = links
+        .iter()
+        .filter(|(predator, prey)| included(predator) && included(prey))
+        .map(|(predator, prey)| FoodWebEdge {
+            predator: predator.to_string(),
+            prey: prey.to_string(),
+        })
+        .collect();
+
+    let nodes = distances
+        .iter()
+        .filter_map(|(node, distance)| node_info(catalog, node, *distance, language))
+        .collect();
+
+    Some(FoodWeb {
+        nodes,
+        edges: edges.into_iter().collect(),
+    })
+}
+
+fn node_info(
+    catalog: &Catalog,
+    node: &NodeRef,
+    distance: u32,
+    language: Language,
+) -> Option<FoodWebNode> {
+    let (fish_id, name, kind) = match node {
+        NodeRef::Fish(id) => {
+            let fish = catalog.get(*id)?;
+            (Some(fish.id), fish.name.get(language), OrganismKind::Fish)
+        }
+        NodeRef::Organism(id) => {
+            let organism = catalog.organism(id)?;
+            (None, organism.name.get(language), organism.kind)
+        }
+    };
+
+    Some(FoodWebNode {
+        id: node.to_string(),
+        fish_id,
+        name: name.to_string(),
+        kind,
+        distance,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // The bundled fish with their prey replaced, and `organisms`:
+    //   orca -> seal -> fish 1 -> krill -> plankton
+    fn catalog(name: &str, organisms: serde_json::Value) -> Result<Catalog, String> {
+        let mut file: serde_json::Value =
+            serde_json::from_str(include_str!("../catalog/fish.json")).unwrap();
+        for fish in file["fish"].as_array_mut().unwrap() {
+            fish["prey"] = json!([]);
+        }
+        file["fish"][0]["prey"] = json!(["krill"]);
+        file["organisms"] = organisms;
+
+        let path =
+            std::env::temp_dir().join(format!("food-web-{}-{}.json", name, std::process::id()));
+        std::fs::write(&path, file.to_string()).unwrap();
+        let catalog = Catalog::load(&path);
+        std::fs::remove_file(&path).ok();
+        let prefix = format!("Failed to parse {} - ", path.display());
+        catalog.map_err(|e| e.strip_prefix(&prefix).unwrap_or(&e).to_string())
+    }
+
+    fn organisms() -> serde_json::Value {
+        json!([
+            { "id": "plankton", "name": "Plankton", "kind": "plankton" },
+            { "id": "krill", "name": "Krill", "kind": "invertebrate", "prey": ["plankton"] },
+            { "id": "seal", "name": "Seal", "kind": "mammal", "prey": [1] },
+            { "id": "orca", "name": "Orca", "kind": "mammal", "prey": ["seal"] }
+        ])
+    }
+
+    fn nodes(web: &FoodWeb) -> Vec<(&str, u32)> {
+        let mut nodes: Vec<_> = web
+            .nodes
+            .iter()
+            .map(|n| (n.id.as_str(), n.distance))
+            .collect();
+        nodes.sort();
+        nodes
+    }
+
+    fn edges(web: &FoodWeb) -> Vec<(&str, &str)> {
+        web.edges
+            .iter()
+            .map(|e| (e.predator.as_str(), e.prey.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn duplicate_organism() {
+        let mut organisms = organisms();
+        organisms
+            .as_array_mut()
+            .unwrap()
+            .push(json!({ "id": "krill", "name": "More krill", "kind": "invertebrate" }));
+        assert_eq!(
+            catalog("duplicate", organisms).err().as_deref(),
+            Some("Organism 'krill' - Duplicate id")
+        );
+    }
+
+    #[test]
+    fn missing_prey() {
+        let mut penguin = organisms();
+        penguin[3]["prey"] = json!(["seal", "penguin"]);
+        assert_eq!(
+            catalog("missing-organism", penguin).err().as_deref(),
+            Some("organism:orca - Prey 'organism:penguin' not found")
+        );
+
+        let mut fish_42 = organisms();
+        fish_42[2]["prey"] = json!([1, 42]);
+        assert_eq!(
+            catalog("missing-fish", fish_42).err().as_deref(),
+            Some("organism:seal - Prey 'fish:42' not found")
+        );
+    }
+
+    #[test]
+    fn neighbourhood_by_depth() {
+        let catalog = catalog("neighbourhood", organisms()).unwrap();
+
+        let web = neighbourhood(&catalog, 1, 0, Language::En).unwrap();
+        assert_eq!(nodes(&web), [("fish:1", 0)]);
+        assert!(web.edges.is_empty());
+
+        // what it eats and what eats it
+        let web = neighbourhood(&catalog, 1, 1, Language::En).unwrap();
+        assert_eq!(
+            nodes(&web),
+            [("fish:1", 0), ("organism:krill", 1), ("organism:seal", 1)]
+        );
+        assert_eq!(
+            edges(&web),
+            [("fish:1", "organism:krill"), ("organism:seal", "fish:1")]
+        );
+
+        let web = neighbourhood(&catalog, 1, 2, Language::En).unwrap();
+        assert_eq!(
+            nodes(&web),
+            [
+                ("fish:1", 0),
+                ("organism:krill", 1),
+                ("organism:orca", 2),
+                ("organism:plankton", 2),
+                ("organism:seal", 1),
+            ]
+        );
+        assert_eq!(
+            edges(&web),
+            [
+                ("fish:1", "organism:krill"),
+                ("organism:krill", "organism:plankton"),
+                ("organism:orca", "organism:seal"),
+                ("organism:seal", "fish:1"),
+            ]
+        );
+
+        // the other fish aren't linked to anything
+        let web = neighbourhood(&catalog, 2, 2, Language::En).unwrap();
+        assert_eq!(nodes(&web), [("fish:2", 0)]);
+        assert!(neighbourhood(&catalog, 42, 1, Language::En).is_none());
+    }
+}
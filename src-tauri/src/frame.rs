@@ -0,0 +1,411 @@
+//! PN532 host link frames (user manual section 6.2.1), kept free of any I/O
+//! so whatever garbage a noisy SPI line hands back can be thrown at them. No
+//! `crate::` imports, the fuzz target in `fuzz/` builds this file on its own.
+//!
+//!   normal    00 00 FF LEN LCS TFI PD0..PDn DCS 00
+//!   extended  00 00 FF FF FF LENM LENL LCS TFI PD0..PDn DCS 00
+//!   ACK       00 00 FF 00 FF 00
+//!   NACK      00 00 FF FF 00 00
+//!   error     00 00 FF 01 FF 7F 81 00
+//!
+//! LEN counts the TFI and PD bytes, which `encode` takes and `decode` gives
+//! back as the frame's data. LEN + LCS and the data + DCS both sum to 0.
+
+use std::fmt;
+
+const PREAMBLE: u8 = 0x00;
+const START_CODE: [u8; 2] = [0x00, 0xFF];
+const POSTAMBLE: u8 = 0x00;
+
+// LEN, LCS of an extended frame
+const EXTENDED_MARKER: [u8; 2] = [0xFF, 0xFF];
+const ACK_MARKER: [u8; 2] = [0x00, 0xFF];
+const NACK_MARKER: [u8; 2] = [0xFF, 0x00];
+// the only data byte of an application level error frame
+const ERROR_DATA: u8 = 0x7F;
+
+/// Most data a normal frame carries, anything longer is extended
+pub const MAX_NORMAL_LEN: usize = 0xFF;
+/// Most data an extended frame carries (the PN532 only buffers 264 bytes)
+pub const MAX_EXTENDED_LEN: usize = 0xFFFF;
+
+pub const ACK: [u8; 6] = [PREAMBLE, 0x00, 0xFF, 0x00, 0xFF, POSTAMBLE];
+pub const NACK: [u8; 6] = [PREAMBLE, 0x00, 0xFF, 0xFF, 0x00, POSTAMBLE];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Ack,
+    Nack,
+    /// The PN532 couldn't make sense of the last frame it was sent
+    Error,
+    /// TFI and packet data
+    Data(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// Nothing but preamble, or something other than the start code
+    NoStartCode,
+    /// Ended before the frame did
+    Truncated,
+    /// LEN and LCS don't add up
+    LengthChecksum,
+    /// The data and DCS don't add up
+    DataChecksum,
+    /// A data frame with no data, not even a TFI
+    Empty,
+    /// More data than an extended frame can carry
+    TooLong,
+}
+
+impl FrameError {
+    /// Whether the frame was garbled on the way, so asking for it again
+    /// might work
+    pub fn is_checksum(self) -> bool {
+        matches!(self, FrameError::LengthChecksum | FrameError::DataChecksum)
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            FrameError::NoStartCode => "no start code",
+            FrameError::Truncated => "frame is truncated",
+            FrameError::LengthChecksum => "length checksum doesn't match",
+            FrameError::DataChecksum => "data checksum doesn't match",
+            FrameError::Empty => "frame has no data",
+            FrameError::TooLong => "frame is too long",
+        };
+        f.write_str(message)
+    }
+}
+
+/// Frame for `data` (TFI and packet data), extended if it won't fit a normal
+/// one
+pub fn encode(data: &[u8]) -> Result<Vec<u8>, FrameError> {
+    if data.is_empty() {
+        return Err(FrameError::Empty);
+    }
+    if data.len() > MAX_EXTENDED_LEN {
+        return Err(FrameError::TooLong);
+    }
+
+    let mut frame = vec![PREAMBLE, START_CODE[0], START_CODE[1]];
+    if data.len() <= MAX_NORMAL_LEN {
+        let len = data.len() as u8;
+        frame.extend_from_slice(&[len, checksum(&[len])]);
+    } else {
+        let len = (data.len() as u16).to_be_bytes();
+        frame.extend_from_slice(&EXTENDED_MARKER);
+        frame.extend_from_slice(&[len[0], len[1], checksum(&len)]);
+    }
+    frame.extend_from_slice(data);
+    frame.push(checksum(data));
+    frame.push(POSTAMBLE);
+    Ok(frame)
+}
+
+/// Bytes to read to be sure of getting a whole frame carrying `data_len`
+/// bytes, postamble included
+pub fn encoded_len(data_len: usize) -> usize {
+    let header = if data_len <= MAX_NORMAL_LEN { 5 } else { 8 };
+    header + data_len + 2
+}
+
+/// Finds the frame in `buf`, skipping any preamble before it. Anything after
+/// the frame (usually the postamble) is ignored.
+pub fn decode(buf: &[u8]) -> Result<Frame, FrameError> {
+    let mut reader = Reader { buf, pos: 0 };
+    while reader.peek() == Some(PREAMBLE) {
+        reader.pos += 1;
+    }
+    match reader.peek() {
+        Some(byte) if byte == START_CODE[1] => reader.pos += 1,
+        // the start code is 00 FF, so zeros then anything else isn't one
+        _ => return Err(FrameError::NoStartCode),
+    }
+
+    let marker = reader.take(2)?;
+    let len = match [marker[0], marker[1]] {
+        ACK_MARKER => return Ok(Frame::Ack),
+        NACK_MARKER => return Ok(Frame::Nack),
+        EXTENDED_MARKER => {
+            let ext = reader.take(3)?;
+            if checksum(ext) != 0 {
+                return Err(FrameError::LengthChecksum);
+            }
+            u16::from_be_bytes([ext[0], ext[1]]) as usize
+        }
+        [len, lcs] => {
+            if len.wrapping_add(lcs) != 0 {
+                return Err(FrameError::LengthChecksum);
+            }
+            len as usize
+        }
+    };
+    if len == 0 {
+        return Err(FrameError::Empty);
+    }
+
+    let data = reader.take(len)?;
+    let dcs = reader.take(1)?[0];
+    if checksum(data) != dcs {
+        return Err(FrameError::DataChecksum);
+    }
+
+    if data == [ERROR_DATA] {
+        return Ok(Frame::Error);
+    }
+    Ok(Frame::Data(data.to_vec()))
+}
+
+// two's complement of the sum, so the bytes and their checksum sum to 0
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes
+        .iter()
+        .fold(0u8, |acc, &b| acc.wrapping_add(b))
+        .wrapping_neg()
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.buf.get(self.pos).copied()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FrameError> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + len)
+            .ok_or(FrameError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::{quickcheck, TestResult};
+
+    // TFI then made up packet data, `len` bytes in all
+    fn data(len: usize) -> Vec<u8> {
+        let mut data = vec![0xD4];
+        data.extend((1..len).map(|i| (i * 31 + 7) as u8));
+        data
+    }
+
+    #[test]
+    fn round_trip() {
+        let lengths = (1..=300).chain([1000, 4096, MAX_EXTENDED_LEN]);
+        for len in lengths {
+            let data = data(len);
+            let frame = encode(&data).unwrap();
+            assert_eq!(frame.len(), encoded_len(len), "length {}", len);
+            assert_eq!(decode(&frame), Ok(Frame::Data(data)), "length {}", len);
+        }
+    }
+
+    #[test]
+    fn normal_and_extended() {
+        assert_eq!(
+            encode(&[0xD4, 0x02]).unwrap(),
+            [0x00, 0x00, 0xFF, 0x02, 0xFE, 0xD4, 0x02, 0x2A, 0x00]
+        );
+
+        let normal = encode(&data(MAX_NORMAL_LEN)).unwrap();
+        assert_eq!(normal[3..5], [0xFF, 0x01]);
+        let extended = encode(&data(MAX_NORMAL_LEN + 1)).unwrap();
+        assert_eq!(extended[3..8], [0xFF, 0xFF, 0x01, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn ack_nack_and_error() {
+        assert_eq!(decode(&ACK), Ok(Frame::Ack));
+        assert_eq!(decode(&NACK), Ok(Frame::Nack));
+        let error = [0x00, 0x00, 0xFF, 0x01, 0xFF, 0x7F, 0x81, 0x00];
+        assert_eq!(decode(&error), Ok(Frame::Error));
+        assert_eq!(encode(&[ERROR_DATA]).unwrap(), error);
+    }
+
+    #[test]
+    fn skips_preamble_and_ignores_trailing_bytes() {
+        let data = data(8);
+        let mut buf = vec![0x00; 5];
+        buf.extend(encode(&data).unwrap());
+        buf.extend([0x00, 0x12, 0x34]);
+        assert_eq!(decode(&buf), Ok(Frame::Data(data)));
+
+        // no postamble is fine too
+        let frame = encode(&[0xD5, 0x15]).unwrap();
+        assert_eq!(
+            decode(&frame[..frame.len() - 1]),
+            Ok(Frame::Data(vec![0xD5, 0x15]))
+        );
+    }
+
+    #[test]
+    fn no_start_code() {
+        assert_eq!(decode(&[]), Err(FrameError::NoStartCode));
+        assert_eq!(decode(&[0x00; 10]), Err(FrameError::NoStartCode));
+        assert_eq!(decode(&[0x12; 10]), Err(FrameError::NoStartCode));
+        assert_eq!(
+            decode(&[0x00, 0x00, 0xFE, 0x02, 0xFE]),
+            Err(FrameError::NoStartCode)
+        );
+    }
+
+    #[test]
+    fn corrupted_length_checksum() {
+        let normal = encode(&data(10)).unwrap();
+        // LEN, LCS
+        for i in 3..5 {
+            let mut frame = normal.clone();
+            frame[i] ^= 0x04;
+            assert_eq!(
+                decode(&frame),
+                Err(FrameError::LengthChecksum),
+                "byte {}",
+                i
+            );
+        }
+
+        let extended = encode(&data(300)).unwrap();
+        // LENM, LENL, LCS
+        for i in 5..8 {
+            let mut frame = extended.clone();
+            frame[i] ^= 0x04;
+            assert_eq!(
+                decode(&frame),
+                Err(FrameError::LengthChecksum),
+                "byte {}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn corrupted_data_checksum() {
+        for (len, start) in [(10, 5), (300, 8)] {
+            let good = encode(&data(len)).unwrap();
+            // every data byte, then the DCS
+            for i in start..good.len() - 1 {
+                let mut frame = good.clone();
+                frame[i] ^= 0x10;
+                assert_eq!(decode(&frame), Err(FrameError::DataChecksum), "byte {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_at_every_offset() {
+        let frames = [
+            encode(&data(10)).unwrap(),
+            encode(&data(300)).unwrap(),
+            ACK.to_vec(),
+            NACK.to_vec(),
+        ];
+        for frame in frames {
+            // cut off before the FF of the start code it's only zeros
+            for end in 0..3 {
+                assert_eq!(decode(&frame[..end]), Err(FrameError::NoStartCode));
+            }
+            // the postamble isn't needed
+            for end in 3..frame.len() - 1 {
+                assert_eq!(
+                    decode(&frame[..end]),
+                    Err(FrameError::Truncated),
+                    "cut at {} of {}",
+                    end,
+                    frame.len()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn encode_errors() {
+        assert_eq!(encode(&[]), Err(FrameError::Empty));
+        assert_eq!(
+            encode(&vec![0; MAX_EXTENDED_LEN + 1]),
+            Err(FrameError::TooLong)
+        );
+        // a zero LEN with a good LCS
+        assert_eq!(
+            decode(&[0x00, 0x00, 0xFF, 0x00, 0x00, 0x00]),
+            Err(FrameError::Empty)
+        );
+    }
+
+    #[test]
+    fn checksum_errors_are_worth_retrying() {
+        assert!(FrameError::LengthChecksum.is_checksum());
+        assert!(FrameError::DataChecksum.is_checksum());
+        assert!(!FrameError::Truncated.is_checksum());
+        assert!(!FrameError::NoStartCode.is_checksum());
+    }
+
+    // what a good frame carrying `data` decodes to
+    fn decoded(data: Vec<u8>) -> Frame {
+        if data == [ERROR_DATA] {
+            Frame::Error
+        } else {
+            Frame::Data(data)
+        }
+    }
+
+    quickcheck! {
+        // with some preamble before and junk after, as the PN532 hands it back
+        fn normal_frames_round_trip(data: Vec<u8>, preamble: u8, after: Vec<u8>) -> TestResult {
+            if data.is_empty() || data.len() > MAX_NORMAL_LEN {
+                return TestResult::discard();
+            }
+            let mut buf = vec![PREAMBLE; preamble as usize % 8];
+            buf.extend(encode(&data).unwrap());
+            buf.extend(after);
+            TestResult::from_bool(decode(&buf) == Ok(decoded(data)))
+        }
+
+        fn extended_frames_round_trip(seed: Vec<u8>, extra: u16) -> bool {
+            let len = MAX_NORMAL_LEN + 1 + extra as usize % (MAX_EXTENDED_LEN - MAX_NORMAL_LEN);
+            let data: Vec<u8> = if seed.is_empty() {
+                (0..len).map(|i| i as u8).collect()
+            } else {
+                seed.iter().copied().cycle().take(len).collect()
+            };
+            let frame = encode(&data).unwrap();
+            frame.len() == encoded_len(len) && decode(&frame) == Ok(Frame::Data(data))
+        }
+
+        // a single garbled byte in the data never gets through as good data
+        fn garbled_data_is_caught(data: Vec<u8>, at: usize, flip: u8) -> TestResult {
+            if data.is_empty() || data.len() > MAX_NORMAL_LEN || flip == 0 {
+                return TestResult::discard();
+            }
+            let mut frame = encode(&data).unwrap();
+            let at = 5 + at % data.len();
+            frame[at] ^= flip;
+            TestResult::from_bool(decode(&frame) == Err(FrameError::DataChecksum))
+        }
+
+        fn decode_never_panics(buf: Vec<u8>) -> bool {
+            let _ = decode(&buf);
+            true
+        }
+
+        // past the start code, where the length and checksums get looked at
+        fn decode_after_a_start_code_never_panics(rest: Vec<u8>, extended: bool) -> bool {
+            let mut buf = vec![PREAMBLE, START_CODE[0], START_CODE[1]];
+            if extended {
+                buf.extend_from_slice(&EXTENDED_MARKER);
+            }
+            buf.extend(rest);
+            let _ = decode(&buf);
+            true
+        }
+    }
+}
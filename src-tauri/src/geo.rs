@@ -0,0 +1,148 @@
+//! Species range maps as GeoJSON geometry. Only `Polygon` and `MultiPolygon`
+//! are accepted, with [longitude, latitude] positions in degrees (WGS 84) as
+//! per RFC 7946. Ranges that wrap around a pole are written as a band across
+//! every longitude, e.g. [-180, 60] -> [180, 60] -> [180, 85] -> [-180, 85].
+
+use serde::{Deserialize, Serialize};
+
+pub type Position = [f64; 2];
+pub type Ring = Vec<Position>;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "coordinates")]
+pub enum Geometry {
+    /// Outer ring followed by any holes
+    Polygon(Vec<Ring>),
+    MultiPolygon(Vec<Vec<Ring>>),
+}
+
+impl Geometry {
+    fn polygons(&self) -> Vec<&[Ring]> {
+        match self {
+            Geometry::Polygon(rings) => vec![rings],
+            Geometry::MultiPolygon(polygons) => polygons.iter().map(Vec::as_slice).collect(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let polygons = self.polygons();
+        if polygons.is_empty() {
+            return Err("MultiPolygon has no polygons".to_string());
+        }
+
+        for (p, rings) in polygons.into_iter().enumerate() {
+            if rings.is_empty() {
+                return Err(format!("Polygon {} has no rings", p));
+            }
+
+            for (r, ring) in rings.iter().enumerate() {
+                validate_ring(ring).map_err(|e| format!("Polygon {} ring {} {}", p, r, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_ring(ring: &Ring) -> Result<(), String> {
+    // a closed ring needs at least a triangle plus the repeated first point
+    if ring.len() < 4 {
+        return Err(format!("has {} positions, needs at least 4", ring.len()));
+    }
+    if ring.first() != ring.last() {
+        return Err("isn't closed (first and last positions differ)".to_string());
+    }
+
+    for &[lon, lat] in ring {
+        if !(-180.0..=180.0).contains(&lon) || !(-90.0..=90.0).contains(&lat) {
+            return Err(format!("has out of range position [{}, {}]", lon, lat));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn polygon(json: &str) -> Geometry {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn valid_ranges() {
+        let square = polygon(
+            r#"{ "type": "Polygon", "coordinates": [[[-75, 40], [30, 40], [30, 72], [-75, 72], [-75, 40]]] }"#,
+        );
+        assert_eq!(square.validate(), Ok(()));
+
+        // a band all the way round, and a hole in it
+        let band = polygon(
+            r#"{ "type": "MultiPolygon", "coordinates": [[
+                [[-180, 60], [180, 60], [180, 85], [-180, 85], [-180, 60]],
+                [[0, 70], [10, 70], [10, 75], [0, 70]]
+            ]] }"#,
+        );
+        assert_eq!(band.validate(), Ok(()));
+    }
+
+    #[test]
+    fn unclosed_ring() {
+        let open = polygon(
+            r#"{ "type": "Polygon", "coordinates": [[[0, 0], [10, 0], [10, 10], [0, 10]]] }"#,
+        );
+        assert_eq!(
+            open.validate(),
+            Err("Polygon 0 ring 0 isn't closed (first and last positions differ)".to_string())
+        );
+    }
+
+    #[test]
+    fn too_few_positions() {
+        let line = polygon(r#"{ "type": "Polygon", "coordinates": [[[0, 0], [10, 0], [0, 0]]] }"#);
+        assert_eq!(
+            line.validate(),
+            Err("Polygon 0 ring 0 has 3 positions, needs at least 4".to_string())
+        );
+
+        let hole = polygon(
+            r#"{ "type": "MultiPolygon", "coordinates": [
+                [[[0, 0], [10, 0], [10, 10], [0, 0]]],
+                [[[20, 0], [30, 0], [30, 10], [20, 0]], []]
+            ] }"#,
+        );
+        assert_eq!(
+            hole.validate(),
+            Err("Polygon 1 ring 1 has 0 positions, needs at least 4".to_string())
+        );
+    }
+
+    #[test]
+    fn out_of_range_positions() {
+        let past_the_pole = polygon(
+            r#"{ "type": "Polygon", "coordinates": [[[0, 80], [10, 80], [10, 95], [0, 80]]] }"#,
+        );
+        assert_eq!(
+            past_the_pole.validate(),
+            Err("Polygon 0 ring 0 has out of range position [10, 95]".to_string())
+        );
+
+        let past_the_antimeridian = polygon(
+            r#"{ "type": "Polygon", "coordinates": [[[170, 0], [190, 0], [190, 10], [170, 0]]] }"#,
+        );
+        assert!(past_the_antimeridian.validate().is_err());
+    }
+
+    #[test]
+    fn empty_geometry() {
+        let none = polygon(r#"{ "type": "MultiPolygon", "coordinates": [] }"#);
+        assert_eq!(
+            none.validate(),
+            Err("MultiPolygon has no polygons".to_string())
+        );
+        let no_rings = polygon(r#"{ "type": "Polygon", "coordinates": [] }"#);
+        assert_eq!(
+            no_rings.validate(),
+            Err("Polygon 0 has no rings".to_string())
+        );
+    }
+}
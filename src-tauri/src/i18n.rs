@@ -0,0 +1,134 @@
+//! Display languages and per-language catalog text.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    En,
+    Fr,
+    Es,
+}
+
+impl Language {
+    /// Parses an IANA language code as found in NDEF text records, ignoring
+    /// case and any region suffix ("en", "fr-CA", "ES").
+    pub fn from_code(code: &str) -> Option<Self> {
+        let primary = code.split(['-', '_']).next()?;
+        match primary.to_ascii_lowercase().as_str() {
+            "en" => Some(Language::En),
+            "fr" => Some(Language::Fr),
+            "es" => Some(Language::Es),
+            _ => None,
+        }
+    }
+}
+
+/// The language to show a fish in: one picked from the UI, then the tag's
+/// language code (with `use_tag_language`), then the kiosk default. Every
+/// text record the kiosk writes is "en", so only another language on a tag
+/// counts as asking for it.
+pub fn display_language(
+    picked: Option<Language>,
+    tag_language: Option<&str>,
+    use_tag_language: bool,
+    default: Language,
+) -> Language {
+    let from_tag = tag_language
+        .filter(|_| use_tag_language)
+        .and_then(Language::from_code)
+        .filter(|&language| language != Language::En);
+    picked.or(from_tag).unwrap_or(default)
+}
+
+/// A piece of catalog text with an English original and optional
+/// translations. In the catalog file it can be written either as a plain
+/// string (English only) or as `{ "en": ..., "fr": ..., "es": ... }`.
+#[derive(Serialize, Debug, Clone)]
+pub struct Localized {
+    pub en: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub es: Option<String>,
+}
+
+impl Localized {
+    /// The text in `language`, falling back to English when there's no
+    /// translation.
+    pub fn get(&self, language: Language) -> &str {
+        let translation = match language {
+            Language::En => None,
+            Language::Fr => self.fr.as_deref(),
+            Language::Es => self.es.as_deref(),
+        };
+        translation.unwrap_or(&self.en)
+    }
+
+    /// The English text followed by every translation there is
+    pub fn all(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.en.as_str())
+            .chain(self.fr.as_deref())
+            .chain(self.es.as_deref())
+    }
+}
+
+impl<'de> Deserialize<'de> for Localized {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            English(String),
+            Translated {
+                en: String,
+                #[serde(default)]
+                fr: Option<String>,
+                #[serde(default)]
+                es: Option<String>,
+            },
+        }
+
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::English(en) => Localized {
+                en,
+                fr: None,
+                es: None,
+            },
+            Repr::Translated { en, fr, es } => Localized { en, fr, es },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picked_then_tag_then_default() {
+        let fr = Language::Fr;
+        assert_eq!(
+            display_language(Some(Language::Es), Some("fr"), true, fr),
+            Language::Es
+        );
+        assert_eq!(
+            display_language(None, Some("es-MX"), true, fr),
+            Language::Es
+        );
+        assert_eq!(display_language(None, None, true, fr), fr);
+        assert_eq!(display_language(None, Some("es"), false, fr), fr);
+        assert_eq!(display_language(None, Some("de"), true, fr), fr);
+    }
+
+    #[test]
+    fn english_tag_keeps_kiosk_default() {
+        assert_eq!(
+            display_language(None, Some("en"), true, Language::Fr),
+            Language::Fr
+        );
+        assert_eq!(
+            display_language(Some(Language::En), Some("fr"), true, Language::Fr),
+            Language::En
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! Inventory of the fish props, so staff can tell when one has gone missing
+//! (into the pond, or home with a visitor). Every tag the scanner reads or
+//! writes is kept in `tag_inventory.json` next to `kiosk.json`, keyed by UID:
+//!
+//!   {
+//!     "04A1B2C3D4E5F6": {
+//!       "fish_id": 3,
+//!       "label": "Big cod, pond 2",
+//!       "enrolled": 1760000000,
+//!       "last_seen": 1760870000,
+//!       "scan_count": 412
+//!     }
+//!   }
+//!
+//! Times are Unix timestamps in seconds.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::json_store;
+use crate::uid_registry::{format_uid, normalize_uid};
+
+pub const INVENTORY_FILE_NAME: &str = "tag_inventory.json";
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InventoryEntry {
+    pub fish_id: u32,
+    /// Staff's name for the prop, e.g. where it lives
+    pub label: Option<String>,
+    pub enrolled: u64,
+    /// `None` until it's first scanned
+    pub last_seen: Option<u64>,
+    pub scan_count: u32,
+}
+
+impl InventoryEntry {
+    fn new(fish_id: u32, enrolled: u64) -> Self {
+        Self {
+            fish_id,
+            label: None,
+            enrolled,
+            last_seen: None,
+            scan_count: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct InventoryItem {
+    pub uid: String,
+    #[serde(flatten)]
+    pub entry: InventoryEntry,
+}
+
+pub struct TagInventory {
+    path: PathBuf,
+    entries: BTreeMap<String, InventoryEntry>,
+}
+
+impl TagInventory {
+    pub fn load(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            entries: json_store::load(path),
+        }
+    }
+
+    /// Adds a prop, or updates its fish and label if it's already there
+    /// (keeping its history), and saves the inventory.
+    pub fn enrol(&mut self, uid: &str, fish_id: u32, label: Option<String>) -> Result<(), String> {
+        let uid = normalize_uid(uid)?;
+        let entry = self
+            .entries
+            .entry(uid)
+            .or_insert_with(|| InventoryEntry::new(fish_id, now()));
+        entry.fish_id = fish_id;
+        if label.is_some() {
+            entry.label = label;
+        }
+        self.save()
+    }
+
+    /// Takes a prop out of the inventory, e.g. once it's been replaced
+    pub fn remove(&mut self, uid: &str) -> Result<(), String> {
+        let uid = normalize_uid(uid)?;
+        if self.entries.remove(&uid).is_none() {
+            return Err(format!("Tag UID {} isn't in the inventory", uid));
+        }
+        self.save()
+    }
+
+    /// Notes a scan of the tag with UID `uid`, enrolling it if it's new
+    pub fn record_scan(&mut self, uid: &[u8], fish_id: u32) -> Result<(), String> {
+        let now = now();
+        let entry = self
+            .entries
+            .entry(format_uid(uid))
+            .or_insert_with(|| InventoryEntry::new(fish_id, now));
+        entry.fish_id = fish_id;
+        entry.last_seen = Some(now);
+        entry.scan_count = entry.scan_count.saturating_add(1);
+        self.save()
+    }
+
+    pub fn items(&self) -> Vec<InventoryItem> {
+        self.entries
+            .iter()
+            .map(|(uid, entry)| InventoryItem {
+                uid: uid.clone(),
+                entry: entry.clone(),
+            })
+            .collect()
+    }
+
+    /// Props not seen in the last `days` days, longest missing first. Props
+    /// never scanned count from when they were enrolled.
+    pub fn missing(&self, days: u32) -> Vec<InventoryItem> {
+        let cutoff = now().saturating_sub(days as u64 * SECONDS_PER_DAY);
+        let mut missing: Vec<InventoryItem> = self
+            .items()
+            .into_iter()
+            .filter(|item| last_seen(&item.entry) < cutoff)
+            .collect();
+        missing.sort_by_key(|item| last_seen(&item.entry));
+        missing
+    }
+
+    fn save(&self) -> Result<(), String> {
+        json_store::save(&self.path, &self.entries)
+    }
+}
+
+fn last_seen(entry: &InventoryEntry) -> u64 {
+    entry.last_seen.unwrap_or(entry.enrolled)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UID: [u8; 7] = [0x04, 0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6];
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("tag-inventory-{}-{}", name, std::process::id()))
+            .join(INVENTORY_FILE_NAME)
+    }
+
+    fn cleanup(path: &Path) {
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    fn uids(items: &[InventoryItem]) -> Vec<&str> {
+        items.iter().map(|item| item.uid.as_str()).collect()
+    }
+
+    #[test]
+    fn enrol_then_scan() {
+        let path = path("scan");
+        let mut inventory = TagInventory::load(&path);
+        inventory
+            .enrol(
+                "04:a1:b2:c3:d4:e5:f6",
+                3,
+                Some("Big cod, pond 2".to_string()),
+            )
+            .unwrap();
+
+        let before = now();
+        inventory.record_scan(&UID, 3).unwrap();
+        inventory.record_scan(&UID, 3).unwrap();
+
+        let items = TagInventory::load(&path).items();
+        assert_eq!(uids(&items), ["04A1B2C3D4E5F6"]);
+        let entry = &items[0].entry;
+        assert_eq!(entry.scan_count, 2);
+        assert!(entry.last_seen >= Some(before));
+        assert!(entry.enrolled <= before);
+
+        // re-enrolling keeps the history, and the label unless there's a new one
+        inventory.enrol("04A1B2C3D4E5F6", 4, None).unwrap();
+        let entry = &inventory.items()[0].entry;
+        assert_eq!(entry.fish_id, 4);
+        assert_eq!(entry.label.as_deref(), Some("Big cod, pond 2"));
+        assert_eq!(entry.scan_count, 2);
+
+        // a scan of a tag nobody enrolled adds it
+        inventory.record_scan(&[0x04, 0x11, 0x22, 0x33], 1).unwrap();
+        assert_eq!(inventory.items().len(), 2);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn missing_after_days() {
+        let path = path("missing");
+        let now = now();
+        let day = SECONDS_PER_DAY;
+        let entries = serde_json::json!({
+            "01000000": { "fish_id": 1, "label": null, "enrolled": now - 30 * day,
+                          "last_seen": now - 2 * day + 60, "scan_count": 5 },
+            "02000000": { "fish_id": 2, "label": null, "enrolled": now - 30 * day,
+                          "last_seen": now - 2 * day - 60, "scan_count": 5 },
+            "03000000": { "fish_id": 3, "label": null, "enrolled": now - 10 * day,
+                          "last_seen": null, "scan_count": 0 },
+            "04000000": { "fish_id": 4, "label": null, "enrolled": now - 60,
+                          "last_seen": null, "scan_count": 0 },
+        });
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, entries.to_string()).unwrap();
+        let inventory = TagInventory::load(&path);
+
+        // never scanned counts from enrolment, longest missing first
+        assert_eq!(uids(&inventory.missing(2)), ["03000000", "02000000"]);
+        assert_eq!(
+            uids(&inventory.missing(0)),
+            ["03000000", "02000000", "01000000", "04000000"]
+        );
+        assert_eq!(uids(&inventory.missing(30)), Vec::<&str>::new());
+        cleanup(&path);
+    }
+
+    #[test]
+    fn remove() {
+        let path = path("remove");
+        let mut inventory = TagInventory::load(&path);
+        inventory.enrol("04A1B2C3D4E5F6", 3, None).unwrap();
+        inventory.enrol("01000000", 1, None).unwrap();
+
+        inventory.remove("04:A1:B2:C3:D4:E5:F6").unwrap();
+        assert_eq!(uids(&TagInventory::load(&path).items()), ["01000000"]);
+        assert_eq!(
+            inventory.remove("04A1B2C3D4E5F6"),
+            Err("Tag UID 04A1B2C3D4E5F6 isn't in the inventory".to_string())
+        );
+        assert!(inventory.remove("not a uid").is_err());
+        cleanup(&path);
+    }
+}
@@ -0,0 +1,74 @@
+//! The small JSON files the kiosk keeps its state in next to `kiosk.json`
+//! (UID registry, prop inventory, tag wear).
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::Path;
+
+/// Contents of `path`, or the default if there's no file yet. A file that
+/// can't be read or parsed is logged and treated as empty, rather than
+/// stopping the kiosk. One that can't be parsed is moved aside to
+/// `*.json.bad` first, so the next save doesn't write over it.
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> T {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            eprintln!("Config: Failed to parse {} - {}", path.display(), e);
+            let bad = path.with_extension("json.bad");
+            match std::fs::rename(path, &bad) {
+                Ok(()) => eprintln!("Config: Moved it to {}", bad.display()),
+                Err(e) => eprintln!("Config: Failed to move it aside - {}", e),
+            }
+            T::default()
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => T::default(),
+        Err(e) => {
+            eprintln!("Config: Failed to read {} - {}", path.display(), e);
+            T::default()
+        }
+    }
+}
+
+/// Writes `value` to `path` as pretty JSON, creating the directory if needed
+pub fn save<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {} - {}", dir.display(), e))?;
+    }
+
+    // write then rename, so a crash mid-write can't lose the whole file
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, json)
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(|e| format!("Failed to write {} - {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn save_then_load() {
+        let dir = std::env::temp_dir().join(format!("json-store-{}", std::process::id()));
+        let path = dir.join("state").join("tags.json");
+
+        let missing: BTreeMap<String, u32> = load(&path);
+        assert!(missing.is_empty());
+
+        let saved = BTreeMap::from([("04A1B2C3D4E5F6".to_string(), 3)]);
+        save(&path, &saved).unwrap();
+        assert_eq!(load::<BTreeMap<String, u32>>(&path), saved);
+        assert!(!path.with_extension("json.tmp").exists());
+
+        // kept for someone to fix rather than saved over
+        std::fs::write(&path, "{ not json").unwrap();
+        assert!(load::<BTreeMap<String, u32>>(&path).is_empty());
+        assert!(!path.exists());
+        let bad = path.with_extension("json.bad");
+        assert_eq!(std::fs::read_to_string(&bad).unwrap(), "{ not json");
+        save(&path, &saved).unwrap();
+        assert_eq!(std::fs::read_to_string(&bad).unwrap(), "{ not json");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,207 +1,648 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use tauri::Emitter;
-
-#[cfg(target_os = "linux")]
+use rand::seq::SliceRandom;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-#[cfg(target_os = "linux")]
-use std::time::Duration;
+use tauri::{Emitter, Manager};
 
-#[cfg(target_os = "linux")]
+mod catalog;
+mod catch;
+mod config;
+mod content;
+mod decoder;
+mod fish_record;
+mod food_web;
+mod frame;
+mod geo;
+mod i18n;
+mod inventory;
+mod json_store;
+mod measurement;
+mod ndef;
 mod pn532;
+mod protection;
+mod reader;
+mod scanner;
+mod scenario;
+mod signature;
+mod simulator;
+mod spi;
+mod tag;
+mod uid_registry;
+mod wear;
+
+use catalog::{Catalog, FishInfo, FishPayload, HabitatPayload};
+use catch::{CatchGenerator, TagCatch};
+use config::{KioskConfig, ScannerBackend, TagFormat};
+use food_web::FoodWeb;
+use i18n::Language;
+use inventory::{InventoryItem, TagInventory};
+use scanner::{ScanEvent, Scanner, ScannerCommand};
+use scenario::Scenario;
+use uid_registry::{UidRegistry, UidRegistryPayload};
+use wear::{WearLog, WearReportItem};
 
 // ###########################################
-//              Fish Data
+//              Fish Display
 // ###########################################
 
-#[derive(serde::Serialize, Debug, Clone)]
-struct FishInfo {
-    id: u32,
-    name: &'static str,
-    average_size: &'static str,
-    average_weight: &'static str,
-    average_lifespan: &'static str,
-    habitat: &'static str,
-    diet: &'static str,
-    endangered_status: &'static str,
-    blurb: &'static str,
-    image_path: &'static str,
-    fun_fact: &'static str,
-}
-
-const FISH_DATA: &[FishInfo] = &[
-    FishInfo {
-        id: 1,
-        name: "Salmon",
-        average_size: "2 to 5 feet",
-        average_weight: "5 to 30 pounds",
-        average_lifespan: "3 to 8 years",
-        habitat: "North Atlantic and Pacific Oceans",
-        diet: "Carnivorous, feeding on insects, zooplankton, crustaceans, and smaller fish",
-        endangered_status: "Least Concern",
-        blurb: "Salmon are anadromous fish, meaning they live in both fresh and saltwater at different stages of their lives. Born in freshwater rivers and streams, they migrate to the ocean to mature before returning to their birthplace to spawn. Known for their rich flavor and high nutritional value, salmon are popular in both commercial and recreational fishing industries. They play a vital role in their ecosystems, supporting other species such as bears, eagles, and humans. Some salmon species can travel hundreds of miles and overcome significant obstacles to reach their spawning grounds, demonstrating remarkable endurance and navigational skills.",
-        image_path: "/salmon.jpg",
-        fun_fact: "Salmon are capable of leaping up to 12 feet in the air to overcome obstacles while migrating upstream to spawn.",
-    },
-    FishInfo {
-        id: 2,
-        name: "Greenland Halibut",
-        average_size: "2 to 3 feet",
-        average_weight: "5 to 40 pounds",
-        average_lifespan: "25 to 30 years",
-        habitat: "Arctic and North Atlantic Oceans",
-        diet: "Carnivorous, feeding on fish, squid, and crustaceans",
-        endangered_status: "Endangered",
-        blurb: "The Greenland halibut, also known as the Greenland turbot, is a deep-sea flatfish found in the frigid waters of the Arctic and North Atlantic. Unlike the Pacific halibut, this species lives at great depths, often between 600 and 2,000 feet. Its diet consists primarily of fish, squid, and crustaceans. Greenland halibut have a distinctive appearance, with their top side being dark and their underside pale, blending well with the ocean floor. They are a significant commercial species in Arctic fisheries and play a vital role in the ecosystem as a predator.",
-        image_path: "/greenland_halibut.jpg",
-        fun_fact: "Greenland halibut are known for their slow growth rate and long lifespan, with some individuals living up to 30 years.",
-    },
-    FishInfo {
-        id: 3,
-        name: "Arctic Cod",
-        average_size: "1 to 2 feet",
-        average_weight: "1 to 3 pounds",
-        average_lifespan: "8 to 12 years",
-        habitat: "Arctic Ocean",
-        diet: "Carnivorous, feeding on zooplankton, small fish, and invertebrates",
-        endangered_status: "Not Evaluated",
-        blurb: "The Arctic cod, also known as polar cod, is a small, silvery fish found in the cold, deep waters of the Arctic Ocean. They play a crucial role in the Arctic food web, serving as a primary food source for larger predators such as seals, whales, and seabirds. Arctic cod are known for their ability to survive in extreme cold temperatures and low light conditions. They have antifreeze proteins in their blood that prevent ice crystals from forming and damaging their cells. Arctic cod are an essential species in the Arctic ecosystem, supporting a diverse range of predators and contributing to the overall health of the marine environment.",
-        image_path: "/polar_cod.jpg",
-        fun_fact: "Arctic cod are capable of surviving in water temperatures as low as -2 degrees Celsius, thanks to their antifreeze proteins.",
-    },
-    FishInfo {
-        id: 4,
-        name: "Antarctic Toothfish",
-        average_size: "4 to 6 feet",
-        average_weight: "50 to 200 pounds",
-        average_lifespan: "10 to 15 years",
-        habitat: "Arctic Ocean",
-        diet: "Carnivorous, feeding on fish, squid, and crustaceans",
-        endangered_status: "Not Evaluated",
-        blurb: "The Antarctic toothfish, also known as the Chilean sea bass, is a large predatory fish found in the cold waters of the Southern Ocean around Antarctica. They are known for their long lifespan, slow growth rate, and high oil content, making them a valuable commercial species. Antarctic toothfish have a unique adaptation called cryopelagic drift, where they release buoyant eggs that drift with the currents to hatch in more favorable conditions. This strategy allows them to reproduce in the harsh Antarctic environment. Antarctic toothfish play a vital role in the Antarctic ecosystem as top predators, controlling the populations of smaller fish and squid.",
-        image_path: "/antarctic_toothfish.jpg",
-        fun_fact: "Antarctic toothfish are known for their deep-sea habitat, living at depths of up to 6,600 feet in the Southern Ocean.",
-    },
-    FishInfo {
-        id: 5,
-        name: "Spiny Icefish",
-        average_size: "6 to 10 inches",
-        average_weight: "Less than 1 pound",
-        average_lifespan: "2 to 3 years",
-        habitat: "Arctic Ocean",
-        diet: "Carnivorous, feeding on krill and small fish",
-        endangered_status: "Not Evaluated",
-        blurb: "The Antarctic spiny icefish is a small, translucent fish found in the cold waters of the Southern Ocean around Antarctica. They are known for their unique adaptation to the extreme cold temperatures of the Antarctic environment. Antarctic spiny icefish lack red blood cells and hemoglobin, which are typically used to transport oxygen in most vertebrates. Instead, they have a specialized protein that binds to oxygen and allows them to survive in oxygen-poor waters. Antarctic spiny icefish play a crucial role in the Antarctic food web, serving as prey for larger predators such as seals, whales, and seabirds.",
-        image_path: "/spiny_icefish.jpg",
-        fun_fact: "Antarctic spiny icefish lack red blood cells and hemoglobin, relying on a specialized protein to transport oxygen in their blood.",
-    },
-];
-
-// ###########################################
-//    NFC Reader (Linux/Raspberry Pi only)
-// ###########################################
-
-// Both real implementatinos for the actual raspberry pi with the reader
-// and the stubs used for debug and development.
-// This only works since I'm developing on a windows machine not a linux
-// machine lol. If you try to develop this from linux the it will try to use a
-// non-existent NFC reader and crash, no such thing as target_os = "raspberry_pi" sadly
-
-#[cfg(target_os = "linux")]
-const TAG_DETECT_TIMEOUT_MS: u64 = 500;
-#[cfg(target_os = "linux")]
-const TAG_REMOVAL_CHECK_MS: u64 = 200;
-#[cfg(target_os = "linux")]
-const TAG_REMOVAL_POLL_MS: u64 = 100;
-#[cfg(target_os = "linux")]
-const MAIN_LOOP_INTERVAL_MS: u64 = 50;
-
-#[cfg(target_os = "linux")]
-fn wait_for_tag_removal(pn532: &mut pn532::Pn532) {
-    while pn532.read_passive_target(TAG_REMOVAL_CHECK_MS).is_some() {
-        thread::sleep(Duration::from_millis(TAG_REMOVAL_POLL_MS));
+/// Language picked from the UI, takes priority over the tag and the kiosk
+/// default until it's cleared again with `set_language(null)`.
+#[derive(Default)]
+struct LanguageOverride(Mutex<Option<Language>>);
+
+fn display_language(app_handle: &tauri::AppHandle, tag_language: Option<&str>) -> Language {
+    let config = app_handle.state::<KioskConfig>();
+    let picked = *app_handle.state::<LanguageOverride>().0.lock().unwrap();
+    i18n::display_language(
+        picked,
+        tag_language,
+        config.use_tag_language,
+        config.default_language,
+    )
+}
+
+fn fish_payload(
+    app_handle: &tauri::AppHandle,
+    fish: &FishInfo,
+    tag_language: Option<&str>,
+) -> FishPayload {
+    let units = app_handle.state::<KioskConfig>().unit_system;
+    let language = display_language(app_handle, tag_language);
+    FishPayload::new(fish, language, units)
+}
+
+fn emit_fish(
+    app_handle: &tauri::AppHandle,
+    fish: &FishInfo,
+    tag_language: Option<&str>,
+    tag_catch: Option<&TagCatch>,
+) -> tauri::Result<()> {
+    let units = app_handle.state::<KioskConfig>().unit_system;
+    let language = display_language(app_handle, tag_language);
+    let catch = app_handle
+        .state::<Mutex<CatchGenerator>>()
+        .lock()
+        .unwrap()
+        .generate(fish, tag_catch, units, language);
+
+    let payload = FishPayload::new(fish, language, units).with_catch(catch);
+    app_handle.emit("fishData", payload)
+}
+
+// ###########################################
+//              NFC Reader
+// ###########################################
+
+// The real PN532 HAT needs the `pn532` feature (and a Raspberry Pi), anywhere
+// else the scanner is simulated so the app still runs in development. With
+// `--replay` the PN532 driver runs against an SPI capture instead.
+
+const SIMULATOR_FLAG: &str = "--simulator";
+const SCENARIO_FLAG: &str = "--scenario";
+const REPLAY_FLAG: &str = "--replay";
+
+// The HAT's SPI and GPIO crates are Linux only, elsewhere the feature does
+// nothing
+const HAS_PN532: bool = cfg!(all(feature = "pn532", target_os = "linux"));
+
+// The command line flags win over the config
+fn scanner_backend(config: &KioskConfig) -> ScannerBackend {
+    if std::env::args().any(|arg| arg == SIMULATOR_FLAG) || flag_value(SCENARIO_FLAG).is_some() {
+        return ScannerBackend::Simulator;
     }
-    println!("Tag removed, ready for next scan.");
+    if config.scanner == ScannerBackend::Pn532 && !HAS_PN532 {
+        println!(
+            "Scanner: Built without the pn532 feature (or not for Linux), using the simulator"
+        );
+        return ScannerBackend::Simulator;
+    }
+    config.scanner
 }
 
-#[cfg(target_os = "linux")]
-fn start_nfc_thread(app_handle: tauri::AppHandle) {
-    thread::spawn(move || {
-        println!("Scanner: Initializing PN532 NFC reader...");
+// The file after `flag`
+fn flag_value(flag: &str) -> Option<PathBuf> {
+    std::env::args()
+        .skip_while(|arg| arg != flag)
+        .nth(1)
+        .map(PathBuf::from)
+}
 
-        let mut pn532 = match pn532::Pn532::new() {
-            Ok(pn532) => pn532,
-            Err(e) => {
-                eprintln!("Scanner: Failed to initialize PN532 - {}", e);
-                return;
-            }
-        };
+#[cfg(all(feature = "pn532", target_os = "linux"))]
+fn pn532_transport(config: &KioskConfig) -> Result<Box<dyn spi::Transport>, String> {
+    let transport = Box::new(spi::SpiTransport::open()?);
+    let Some(path) = &config.spi_capture else {
+        return Ok(transport);
+    };
+    println!("Scanner: Capturing SPI traffic to {}", path.display());
+    Ok(Box::new(spi::CaptureTransport::create(transport, path)?))
+}
 
-        if !pn532.sam_config() {
-            eprintln!("Scanner: Failed to configure PN532 SAM");
+// Runs the scanner with a PN532 on `transport`, until nothing's listening
+fn run_pn532(
+    transport: Result<Box<dyn spi::Transport>, String>,
+    handler: reader::TagHandler,
+    events: mpsc::Sender<ScanEvent>,
+    commands: mpsc::Receiver<ScannerCommand>,
+) {
+    let offline = |reason: String| {
+        let _ = events.send(ScanEvent::ReaderOffline { reason });
+    };
+    let mut pn532 = match transport {
+        Ok(transport) => pn532::Pn532::new(transport),
+        Err(e) => {
+            offline(format!("Failed to initialize PN532 - {}", e));
             return;
         }
+    };
 
-        println!("Scanner: PN532 initialized successfully. Scanning for tags...");
+    if !pn532.sam_config() {
+        offline("Failed to configure PN532 SAM".to_string());
+        return;
+    }
+
+    println!("Scanner: PN532 initialized successfully. Scanning for tags...");
 
-        loop {
-            if pn532.read_passive_target(TAG_DETECT_TIMEOUT_MS).is_none() {
-                thread::sleep(Duration::from_millis(MAIN_LOOP_INTERVAL_MS));
-                continue;
+    let reader = reader::Pn532Reader::new(pn532, handler);
+    Scanner::new(reader, events, commands).run();
+}
+
+fn start_nfc_thread(app_handle: tauri::AppHandle) {
+    let (command_tx, command_rx) = mpsc::channel();
+    app_handle.manage(ScannerCommands(command_tx));
+    let (event_tx, event_rx) = mpsc::channel();
+
+    let config = app_handle.state::<KioskConfig>().inner().clone();
+    let registry = app_handle
+        .state::<Arc<Mutex<UidRegistry>>>()
+        .inner()
+        .clone();
+    let backend = scanner_backend(&config);
+    let replay = flag_value(REPLAY_FLAG);
+
+    // only tags on a real reader go in the prop inventory and wear log,
+    // simulated and replayed ones would fill them with props that aren't there
+    let simulated = replay.is_some() || !(HAS_PN532 && backend == ScannerBackend::Pn532);
+    let wear_log = app_handle.state::<Arc<Mutex<WearLog>>>().inner().clone();
+    let handler =
+        reader::TagHandler::new(config.clone(), registry, (!simulated).then_some(wear_log));
+
+    match (replay, backend) {
+        (Some(path), _) => {
+            println!("Scanner: Replaying SPI capture {}", path.display());
+            thread::spawn(move || {
+                let transport = spi::ReplayTransport::load(&path)
+                    .map(|replay| Box::new(replay) as Box<dyn spi::Transport>);
+                run_pn532(transport, handler, event_tx, command_rx);
+            });
+        }
+        #[cfg(all(feature = "pn532", target_os = "linux"))]
+        (None, ScannerBackend::Pn532) => {
+            thread::spawn(move || {
+                println!("Scanner: Initializing PN532 NFC reader...");
+                run_pn532(pn532_transport(&config), handler, event_tx, command_rx);
+            });
+        }
+        _ => {
+            println!("Scanner: Running with a simulated NFC reader");
+            let fish = app_handle.state::<Catalog>().fish().to_vec();
+            let reader = simulator::SimulatedReader::new(handler, &fish, config.tag_format);
+
+            let scenario = flag_value(SCENARIO_FLAG)
+                .or(config.scenario)
+                .and_then(|path| match Scenario::load(&path, &fish) {
+                    Ok(scenario) => {
+                        println!("Scanner: Playing scenario {}", path.display());
+                        Some(scenario)
+                    }
+                    Err(e) => {
+                        eprintln!("Scanner: Failed to load scenario - {}", e);
+                        None
+                    }
+                });
+            match scenario {
+                Some(scenario) => {
+                    let reader = scenario::ScenarioReader::new(reader, scenario);
+                    thread::spawn(move || Scanner::new(reader, event_tx, command_rx).run());
+                }
+                None => {
+                    thread::spawn(move || Scanner::new(reader, event_tx, command_rx).run());
+                }
             }
+        }
+    }
 
-            let Some(fish_id) = pn532.read_fish_id() else {
-                wait_for_tag_removal(&mut pn532);
-                continue;
-            };
+    thread::spawn(move || {
+        for event in event_rx {
+            handle_scan_event(&app_handle, event, simulated);
+        }
+    });
+}
 
-            let Some(fish) = FISH_DATA.iter().find(|f| f.id == fish_id) else {
-                eprintln!("Scanner: Fish ID '{}' - Not Found", fish_id);
-                wait_for_tag_removal(&mut pn532);
-                continue;
+// Forwards what the scanner saw to the UI. `simulated` events aren't from
+// real props, so they're kept out of the inventory.
+fn handle_scan_event(app_handle: &tauri::AppHandle, event: ScanEvent, simulated: bool) {
+    match event {
+        ScanEvent::Fish { uid, tag } => show_tag(app_handle, &uid, tag, simulated),
+        ScanEvent::UnknownTag { uid } => {
+            let registry = app_handle.state::<Arc<Mutex<UidRegistry>>>();
+            registry.lock().unwrap().note_unknown(&uid);
+        }
+        ScanEvent::TagRejected { uid, reason } => {
+            eprintln!(
+                "Scanner: Tag UID {} - Rejected - {}",
+                uid_registry::format_uid(&uid),
+                reason
+            );
+        }
+        ScanEvent::ReadFailed { uid, reason } => {
+            eprintln!(
+                "Scanner: Tag UID {} - Failed to read - {}",
+                uid_registry::format_uid(&uid),
+                reason
+            );
+        }
+        ScanEvent::TagWritten {
+            uid,
+            fish_id,
+            result,
+        } => {
+            match &result {
+                Ok(()) if simulated => {
+                    println!("Scanner: Fish ID '{}' - Simulated tag written", fish_id);
+                }
+                Ok(()) => {
+                    println!("Scanner: Fish ID '{}' - Tag written", fish_id);
+                    let uid = uid_registry::format_uid(&uid);
+                    let inventory = app_handle.state::<Mutex<TagInventory>>();
+                    let enrolled = inventory.lock().unwrap().enrol(&uid, fish_id, None);
+                    if let Err(e) = enrolled {
+                        eprintln!("Scanner: Failed to update tag inventory - {}", e);
+                    }
+                }
+                Err(e) => eprintln!(
+                    "Scanner: Fish ID '{}' - Failed to write tag - {}",
+                    fish_id, e
+                ),
+            }
+            let result = TagWriteResult {
+                id: fish_id,
+                error: result.err(),
             };
-
-            if let Err(error) = app_handle.emit("fishData", fish) {
+            if let Err(error) = app_handle.emit("tagWritten", result) {
                 eprintln!("Scanner: Fish ID '{}' - Failed to Emit {}", fish_id, error);
-                return;
             }
+        }
+        ScanEvent::TagRemoved { uid } => println!(
+            "Tag {} removed, ready for next scan.",
+            uid_registry::format_uid(&uid)
+        ),
+        ScanEvent::ReaderOffline { reason } => {
+            eprintln!("Scanner: Reader offline - {}", reason);
+            if let Err(error) = app_handle.emit("readerOffline", reason) {
+                eprintln!("Scanner: Failed to Emit {}", error);
+            }
+        }
+    }
+}
 
-            println!("Scanner: {} ({})", fish.name, fish_id);
+fn show_tag(app_handle: &tauri::AppHandle, uid: &[u8], tag: tag::FishTag, simulated: bool) {
+    let fish_id = tag.fish_id;
 
-            wait_for_tag_removal(&mut pn532);
+    if !simulated {
+        let inventory = app_handle.state::<Mutex<TagInventory>>();
+        let recorded = inventory.lock().unwrap().record_scan(uid, fish_id);
+        if let Err(e) = recorded {
+            eprintln!("Scanner: Failed to update tag inventory - {}", e);
         }
-    });
+    }
+
+    // the catalog entry wins over whatever a self-describing tag says
+    let catalog = app_handle.state::<Catalog>();
+    let fish = match (catalog.get(fish_id), tag.fish.as_deref()) {
+        (Some(fish), _) => fish,
+        (None, Some(fish)) => {
+            println!("Scanner: Fish ID '{}' - Showing record from tag", fish_id);
+            fish
+        }
+        (None, None) => {
+            eprintln!("Scanner: Fish ID '{}' - Not Found", fish_id);
+            return;
+        }
+    };
+
+    let language = tag.language.as_deref();
+    if let Err(error) = emit_fish(app_handle, fish, language, tag.catch.as_ref()) {
+        eprintln!("Scanner: Fish ID '{}' - Failed to Emit {}", fish_id, error);
+        return;
+    }
+
+    println!("Scanner: {} ({})", fish.name.en, fish_id);
 }
 
-/// Stub for debug builds without NFC hardware :D
-#[cfg(not(target_os = "linux"))]
-fn start_nfc_thread(_app_handle: tauri::AppHandle) {
-    println!("Scanner: NFC reader not available on this platform");
-    println!("Scanner: Running in development mode without NFC hardware.");
+// ###########################################
+//              Tag Writing
+// ###########################################
+
+/// Sends write requests to the scanner thread
+struct ScannerCommands(mpsc::Sender<ScannerCommand>);
+
+#[derive(serde::Serialize, Clone)]
+struct TagWriteResult {
+    id: u32,
+    error: Option<String>,
+}
+
+/// Writes fish `id` to the next tag held to the reader, in `format` or the
+/// kiosk's `tag_format` if not given. A `tagWritten` event follows once it's
+/// done.
+#[tauri::command]
+fn write_fish_tag(
+    app_handle: tauri::AppHandle,
+    id: u32,
+    format: Option<TagFormat>,
+) -> Result<(), String> {
+    let catalog = app_handle.state::<Catalog>();
+    let fish = catalog
+        .get(id)
+        .ok_or_else(|| format!("Fish ID '{}' - Not Found", id))?;
+    let format = format.unwrap_or(app_handle.state::<KioskConfig>().tag_format);
+
+    let command = ScannerCommand::WriteTag {
+        fish: fish.clone(),
+        format,
+    };
+    // the scanner thread drops its end if the reader failed to start
+    app_handle
+        .state::<ScannerCommands>()
+        .0
+        .send(command)
+        .map_err(|_| "NFC reader isn't running".to_string())?;
+
+    println!(
+        "Scanner: Fish ID '{}' - Hold a tag to the reader to write it",
+        id
+    );
+    Ok(())
 }
 
 // ###########################################
-//              Debug Commands
+//              Tag UID Registry
+// ###########################################
+
+/// Enrolled tag UIDs and recently scanned unknown ones
+#[tauri::command]
+fn list_tag_uids(registry: tauri::State<Arc<Mutex<UidRegistry>>>) -> UidRegistryPayload {
+    registry.lock().unwrap().payload()
+}
+
+/// Maps tag `uid` (hex, separators allowed) to fish `fish_id`, or removes it
+/// from the registry when `fish_id` is null.
+#[tauri::command]
+fn set_tag_uid(
+    app_handle: tauri::AppHandle,
+    uid: String,
+    fish_id: Option<u32>,
+) -> Result<(), String> {
+    if let Some(id) = fish_id {
+        if app_handle.state::<Catalog>().get(id).is_none() {
+            return Err(format!("Fish ID '{}' - Not Found", id));
+        }
+    }
+
+    let registry = app_handle.state::<Arc<Mutex<UidRegistry>>>();
+    registry.lock().unwrap().set(&uid, fish_id)?;
+    match fish_id {
+        Some(id) => println!("Scanner: Tag UID {} - Enrolled as Fish ID '{}'", uid, id),
+        None => println!("Scanner: Tag UID {} - Removed", uid),
+    }
+    Ok(())
+}
+
 // ###########################################
+//              Tag Inventory
+// ###########################################
+
+/// Every prop in the inventory
+#[tauri::command]
+fn list_tag_inventory(inventory: tauri::State<Mutex<TagInventory>>) -> Vec<InventoryItem> {
+    inventory.lock().unwrap().items()
+}
+
+/// Props that haven't been scanned in the last `days` days, longest missing
+/// first, so staff know what to replace
+#[tauri::command]
+fn missing_tags(inventory: tauri::State<Mutex<TagInventory>>, days: u32) -> Vec<InventoryItem> {
+    inventory.lock().unwrap().missing(days)
+}
 
+/// Adds tag `uid` to the inventory as fish `fish_id`, or updates it
 #[tauri::command]
-fn debug_scan_random_fish(app_handle: tauri::AppHandle) {
-    let random_fish_id = (rand::random::<u32>() % FISH_DATA.len() as u32) + 1;
+fn enrol_tag(
+    app_handle: tauri::AppHandle,
+    uid: String,
+    fish_id: u32,
+    label: Option<String>,
+) -> Result<(), String> {
+    if app_handle.state::<Catalog>().get(fish_id).is_none() {
+        return Err(format!("Fish ID '{}' - Not Found", fish_id));
+    }
+
+    let inventory = app_handle.state::<Mutex<TagInventory>>();
+    inventory.lock().unwrap().enrol(&uid, fish_id, label)?;
+    println!(
+        "Scanner: Tag UID {} - Added to inventory as Fish ID '{}'",
+        uid, fish_id
+    );
+    Ok(())
+}
+
+/// Takes tag `uid` out of the inventory, e.g. once it's been replaced
+#[tauri::command]
+fn remove_tag(inventory: tauri::State<Mutex<TagInventory>>, uid: String) -> Result<(), String> {
+    inventory.lock().unwrap().remove(&uid)?;
+    println!("Scanner: Tag UID {} - Removed from inventory", uid);
+    Ok(())
+}
+
+// ###########################################
+//              Tag Diagnostics
+// ###########################################
 
-    let Some(fish) = FISH_DATA.iter().find(|f| f.id == random_fish_id) else {
-        unreachable!("Scanner: Fish ID '{}' - Not Found", random_fish_id);
+/// Read problems and NFC counters of every tag scanned, tags with the most
+/// recent problems first. Tags flagged `worn` should be replaced.
+#[tauri::command]
+fn tag_wear_report(wear_log: tauri::State<Arc<Mutex<WearLog>>>) -> Vec<WearReportItem> {
+    wear_log.lock().unwrap().report()
+}
+
+// ###########################################
+//              Debug Commands
+// ###########################################
+
+// These go straight to `handle_scan_event` as if the scanner had seen them,
+// whatever reader is running, but as simulated scans so their made-up UIDs
+// stay out of the prop inventory. Refused in release builds so nothing on the
+// kiosk can fake a scan.
+
+/// UID of the last tag a debug command "presented", for `debug_remove_tag`
+#[derive(Default)]
+struct DebugTag(Mutex<Option<Vec<u8>>>);
+
+fn debug_event(app_handle: &tauri::AppHandle, event: ScanEvent) -> Result<(), String> {
+    if cfg!(not(debug_assertions)) {
+        return Err("Debug commands are disabled in release builds".to_string());
+    }
+
+    // a debug command "presenting" a tag takes away the last one
+    let presented = match &event {
+        ScanEvent::Fish { uid, .. }
+        | ScanEvent::UnknownTag { uid }
+        | ScanEvent::ReadFailed { uid, .. } => Some(uid.clone()),
+        _ => None,
     };
+    *app_handle.state::<DebugTag>().0.lock().unwrap() = presented;
 
-    if let Err(error) = app_handle.emit("fishData", fish) {
-        eprintln!(
-            "Scanner: Fish ID '{}' - Failed to Emit {}",
-            random_fish_id, error
-        );
-        return;
+    handle_scan_event(app_handle, event, true);
+    Ok(())
+}
+
+#[tauri::command]
+fn debug_scan_random_fish(app_handle: tauri::AppHandle) -> Result<(), String> {
+    let fish_id = app_handle
+        .state::<Catalog>()
+        .fish()
+        .choose(&mut rand::thread_rng())
+        .ok_or("Catalog is empty")?
+        .id;
+    debug_scan_fish(app_handle, fish_id)
+}
+
+#[tauri::command]
+fn debug_scan_fish(app_handle: tauri::AppHandle, fish_id: u32) -> Result<(), String> {
+    if app_handle.state::<Catalog>().get(fish_id).is_none() {
+        return Err(format!("Fish ID '{}' isn't in the catalog", fish_id));
     }
+    let event = ScanEvent::Fish {
+        uid: simulator::simulated_uid(fish_id),
+        tag: tag::FishTag::from_id(fish_id),
+    };
+    debug_event(&app_handle, event)
+}
 
-    println!("Scanner: {} ({})", fish.name, random_fish_id);
+/// Takes away the tag from the last debug scan
+#[tauri::command]
+fn debug_remove_tag(app_handle: tauri::AppHandle) -> Result<(), String> {
+    let uid = app_handle.state::<DebugTag>().0.lock().unwrap().clone();
+    let uid = uid.ok_or("No tag to remove")?;
+    debug_event(&app_handle, ScanEvent::TagRemoved { uid })
+}
+
+#[tauri::command]
+fn debug_unknown_tag(app_handle: tauri::AppHandle) -> Result<(), String> {
+    let uid = simulator::BLANK_UID.to_vec();
+    debug_event(&app_handle, ScanEvent::UnknownTag { uid })
+}
+
+#[tauri::command]
+fn debug_read_failure(app_handle: tauri::AppHandle) -> Result<(), String> {
+    let event = ScanEvent::ReadFailed {
+        uid: simulator::BLANK_UID.to_vec(),
+        reason: "Simulated read failure".to_string(),
+    };
+    debug_event(&app_handle, event)
+}
+
+#[tauri::command]
+fn debug_reader_offline(app_handle: tauri::AppHandle) -> Result<(), String> {
+    let event = ScanEvent::ReaderOffline {
+        reason: "Simulated".to_string(),
+    };
+    debug_event(&app_handle, event)
+}
+
+// ###########################################
+//              Catalog Commands
+// ###########################################
+
+#[tauri::command]
+fn list_fish(app_handle: tauri::AppHandle) -> Vec<FishPayload> {
+    let catalog = app_handle.state::<Catalog>();
+    catalog
+        .fish()
+        .iter()
+        .map(|fish| fish_payload(&app_handle, fish, None))
+        .collect()
+}
+
+#[tauri::command]
+fn get_fish(app_handle: tauri::AppHandle, id: u32) -> Option<FishPayload> {
+    let catalog = app_handle.state::<Catalog>();
+    catalog
+        .get(id)
+        .map(|fish| fish_payload(&app_handle, fish, None))
+}
+
+#[tauri::command]
+fn search_fish(app_handle: tauri::AppHandle, query: String) -> Vec<FishPayload> {
+    let catalog = app_handle.state::<Catalog>();
+    catalog
+        .search(&query)
+        .into_iter()
+        .map(|fish| fish_payload(&app_handle, fish, None))
+        .collect()
+}
+
+/// Fish related to `id` (same family, same waters...) for "you might also
+/// catch...", empty if there's no fish with that id.
+#[tauri::command]
+fn get_related_fish(app_handle: tauri::AppHandle, id: u32) -> Vec<FishPayload> {
+    let catalog = app_handle.state::<Catalog>();
+    catalog
+        .related(id)
+        .into_iter()
+        .map(|fish| fish_payload(&app_handle, fish, None))
+        .collect()
+}
+
+const DEFAULT_FOOD_WEB_DEPTH: u32 = 1;
+
+/// The food web around fish `id` as a graph: everything within `depth`
+/// predator/prey links of it (default 1, its direct predators and prey).
+#[tauri::command]
+fn get_food_web(app_handle: tauri::AppHandle, id: u32, depth: Option<u32>) -> Option<FoodWeb> {
+    let catalog = app_handle.state::<Catalog>();
+    let language = display_language(&app_handle, None);
+    food_web::neighbourhood(
+        &catalog,
+        id,
+        depth.unwrap_or(DEFAULT_FOOD_WEB_DEPTH),
+        language,
+    )
+}
+
+/// Habitat range (GeoJSON) and depth range of fish `id`, for the map screen
+#[tauri::command]
+fn get_habitat(app_handle: tauri::AppHandle, id: u32) -> Option<HabitatPayload> {
+    let catalog = app_handle.state::<Catalog>();
+    let units = app_handle.state::<KioskConfig>().unit_system;
+    let language = display_language(&app_handle, None);
+    catalog
+        .get(id)
+        .map(|fish| HabitatPayload::new(fish, language, units))
+}
+
+// ###########################################
+//              Display Commands
+// ###########################################
+
+/// Switches the display language, or goes back to picking it from the tag
+/// and kiosk default when `language` is null.
+#[tauri::command]
+fn set_language(language: Option<Language>, state: tauri::State<LanguageOverride>) {
+    *state.0.lock().unwrap() = language;
 }
 
 // ###########################################
@@ -210,8 +651,52 @@ fn debug_scan_random_fish(app_handle: tauri::AppHandle) {
 
 fn main() {
     tauri::Builder::default()
-        .invoke_handler(tauri::generate_handler![debug_scan_random_fish])
+        .manage(LanguageOverride::default())
+        .manage(DebugTag::default())
+        .invoke_handler(tauri::generate_handler![
+            debug_scan_random_fish,
+            debug_scan_fish,
+            debug_remove_tag,
+            debug_unknown_tag,
+            debug_read_failure,
+            debug_reader_offline,
+            list_fish,
+            get_fish,
+            search_fish,
+            get_related_fish,
+            get_food_web,
+            get_habitat,
+            set_language,
+            write_fish_tag,
+            list_tag_uids,
+            set_tag_uid,
+            list_tag_inventory,
+            missing_tags,
+            enrol_tag,
+            remove_tag,
+            tag_wear_report
+        ])
+        .register_uri_scheme_protocol(content::SCHEME, |ctx, request| {
+            content::handle_request(&ctx.app_handle().state::<Catalog>(), &request)
+        })
         .setup(|app| {
+            let config_dir = app.path().app_config_dir()?;
+            let config = KioskConfig::load(&config_dir.join(config::CONFIG_FILE_NAME));
+            let registry_path = config_dir.join(uid_registry::REGISTRY_FILE_NAME);
+            // shared with the UID decoder on the scanner thread
+            app.manage(Arc::new(Mutex::new(UidRegistry::load(&registry_path))));
+            let inventory_path = config_dir.join(inventory::INVENTORY_FILE_NAME);
+            app.manage(Mutex::new(TagInventory::load(&inventory_path)));
+            let wear_log = WearLog::load(&config_dir.join(wear::WEAR_FILE_NAME));
+            wear_log.log_worn();
+            // shared with the reader on the scanner thread
+            app.manage(Arc::new(Mutex::new(wear_log)));
+
+            let bundled_content_dir = app.path().resource_dir()?.join("catalog");
+            app.manage(Catalog::from_config(&config, bundled_content_dir));
+            app.manage(Mutex::new(CatchGenerator::new(config.catch_seed)));
+            app.manage(config);
+
             start_nfc_thread(app.handle().clone());
             Ok(())
         })
@@ -0,0 +1,367 @@
+//! Structured fish measurements (size, weight, lifespan) and formatting them
+//! for display in the kiosk's configured unit system.
+
+use serde::{Deserialize, Serialize};
+
+use crate::i18n::Language;
+
+const CM_PER_INCH: f64 = 2.54;
+const M_PER_FOOT: f64 = 0.3048;
+const KG_PER_POUND: f64 = 0.453_592_37;
+
+/// Which units the display text is written in. `Both` shows imperial first
+/// with metric in brackets, e.g. "2 to 5 feet (0.6 to 1.5 metres)".
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UnitSystem {
+    #[default]
+    Imperial,
+    Metric,
+    Both,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Unit {
+    Inches,
+    Feet,
+    Pounds,
+    Centimetres,
+    Metres,
+    Kilograms,
+    Years,
+}
+
+impl Unit {
+    fn name(self, value: f64, language: Language) -> &'static str {
+        // French keeps the singular below 2, "0,5 kilogramme"
+        let singular = match language {
+            Language::Fr => value.abs() < 2.0,
+            Language::En | Language::Es => value == 1.0,
+        };
+        let (one, many) = match (self, language) {
+            (Unit::Inches, Language::En) => ("inch", "inches"),
+            (Unit::Inches, Language::Fr) => ("pouce", "pouces"),
+            (Unit::Inches, Language::Es) => ("pulgada", "pulgadas"),
+            (Unit::Feet, Language::En) => ("foot", "feet"),
+            (Unit::Feet, Language::Fr) => ("pied", "pieds"),
+            (Unit::Feet, Language::Es) => ("pie", "pies"),
+            (Unit::Pounds, Language::En) => ("pound", "pounds"),
+            (Unit::Pounds, Language::Fr) => ("livre", "livres"),
+            (Unit::Pounds, Language::Es) => ("libra", "libras"),
+            (Unit::Centimetres, Language::En) => ("centimetre", "centimetres"),
+            (Unit::Centimetres, Language::Fr) => ("centimètre", "centimètres"),
+            (Unit::Centimetres, Language::Es) => ("centímetro", "centímetros"),
+            (Unit::Metres, Language::En) => ("metre", "metres"),
+            (Unit::Metres, Language::Fr) => ("mètre", "mètres"),
+            (Unit::Metres, Language::Es) => ("metro", "metros"),
+            (Unit::Kilograms, Language::En) => ("kilogram", "kilograms"),
+            (Unit::Kilograms, Language::Fr) => ("kilogramme", "kilogrammes"),
+            (Unit::Kilograms, Language::Es) => ("kilogramo", "kilogramos"),
+            (Unit::Years, Language::En) => ("year", "years"),
+            (Unit::Years, Language::Fr) => ("an", "ans"),
+            (Unit::Years, Language::Es) => ("año", "años"),
+        };
+        if singular {
+            one
+        } else {
+            many
+        }
+    }
+
+    // Metric equivalent and the factor to get there, units that are already
+    // metric (or unitless like years) map to themselves
+    fn to_metric(self) -> (Unit, f64) {
+        match self {
+            Unit::Inches => (Unit::Centimetres, CM_PER_INCH),
+            Unit::Feet => (Unit::Metres, M_PER_FOOT),
+            Unit::Pounds => (Unit::Kilograms, KG_PER_POUND),
+            Unit::Centimetres | Unit::Metres | Unit::Kilograms | Unit::Years => (self, 1.0),
+        }
+    }
+
+    // The other way round, imperial units (and years) map to themselves
+    fn to_imperial(self) -> (Unit, f64) {
+        match self {
+            Unit::Centimetres => (Unit::Inches, 1.0 / CM_PER_INCH),
+            Unit::Metres => (Unit::Feet, 1.0 / M_PER_FOOT),
+            Unit::Kilograms => (Unit::Pounds, 1.0 / KG_PER_POUND),
+            Unit::Inches | Unit::Feet | Unit::Pounds | Unit::Years => (self, 1.0),
+        }
+    }
+
+    // Smallest unit of the same kind and the factor to get there, so any two
+    // lengths (or weights) can be compared
+    fn to_base(self) -> (Unit, f64) {
+        match self {
+            Unit::Metres => (Unit::Centimetres, 100.0),
+            Unit::Feet => (Unit::Centimetres, M_PER_FOOT * 100.0),
+            _ => self.to_metric(),
+        }
+    }
+}
+
+/// A measurement range as it's stored in the catalog. `min` is `None` for
+/// open ranges like "Less than 1 pound".
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    pub min: Option<f64>,
+    pub max: f64,
+    pub unit: Unit,
+}
+
+impl Measurement {
+    pub fn to_metric(self) -> Self {
+        self.scaled(self.unit.to_metric())
+    }
+
+    pub fn to_imperial(self) -> Self {
+        self.scaled(self.unit.to_imperial())
+    }
+
+    fn scaled(self, (unit, factor): (Unit, f64)) -> Self {
+        Self {
+            min: self.min.map(|min| min * factor),
+            max: self.max * factor,
+            unit,
+        }
+    }
+
+    /// `Err` unless it's a range of positive values with `min` below `max`,
+    /// anything else can't be drawn from or scaled along
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.max > 0.0 && self.max.is_finite()) {
+            return Err(format!("max {} isn't above 0", self.max));
+        }
+        match self.min {
+            Some(min) if !(0.0..self.max).contains(&min) => {
+                Err(format!("min {} isn't between 0 and max {}", min, self.max))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// In `system` whichever units it's stored in
+    pub fn format(&self, system: UnitSystem, language: Language) -> String {
+        let text = |m: &Measurement| match m.min {
+            Some(_) => m.values(language),
+            None => format!("{} {}", less_than(language), m.values(language)),
+        };
+
+        let (imperial, metric) = (self.to_imperial(), self.to_metric());
+        match system {
+            UnitSystem::Imperial => text(&imperial),
+            UnitSystem::Metric => text(&metric),
+            // years are years either way
+            UnitSystem::Both if imperial.unit == metric.unit => text(&metric),
+            UnitSystem::Both => format!("{} ({})", text(&imperial), metric.values(language)),
+        }
+    }
+
+    // The numbers and unit without the "Less than" prefix for open ranges
+    fn values(&self, language: Language) -> String {
+        let max = round_for_display(self.max);
+        let max_text = number(max, language);
+        match self.min {
+            Some(min) => format!(
+                "{} {} {} {}",
+                number(round_for_display(min), language),
+                range_word(language),
+                max_text,
+                self.unit.name(max, language)
+            ),
+            None => format!("{} {}", max_text, self.unit.name(max, language)),
+        }
+    }
+}
+
+/// A single value rather than a range, e.g. the length of one particular
+/// fish.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Quantity {
+    pub value: f64,
+    pub unit: Unit,
+}
+
+impl Quantity {
+    pub fn to_metric(self) -> Self {
+        self.scaled(self.unit.to_metric())
+    }
+
+    pub fn to_imperial(self) -> Self {
+        self.scaled(self.unit.to_imperial())
+    }
+
+    fn scaled(self, (unit, factor): (Unit, f64)) -> Self {
+        Self {
+            value: self.value * factor,
+            unit,
+        }
+    }
+
+    /// The same quantity in `unit`, or `None` if they measure different
+    /// things (a length in pounds)
+    pub fn convert(self, unit: Unit) -> Option<f64> {
+        let (base, factor) = self.unit.to_base();
+        let (target_base, target_factor) = unit.to_base();
+        (base == target_base).then(|| self.value * factor / target_factor)
+    }
+
+    /// In `system` whichever units it's stored in
+    pub fn format(&self, system: UnitSystem, language: Language) -> String {
+        let (imperial, metric) = (self.to_imperial(), self.to_metric());
+        match system {
+            UnitSystem::Imperial => imperial.text(language),
+            UnitSystem::Metric => metric.text(language),
+            UnitSystem::Both if imperial.unit == metric.unit => metric.text(language),
+            UnitSystem::Both => format!("{} ({})", imperial.text(language), metric.text(language)),
+        }
+    }
+
+    fn text(&self, language: Language) -> String {
+        let value = round_for_display(self.value);
+        format!(
+            "{} {}",
+            number(value, language),
+            self.unit.name(value, language)
+        )
+    }
+}
+
+fn range_word(language: Language) -> &'static str {
+    match language {
+        Language::En => "to",
+        Language::Fr => "à",
+        Language::Es => "a",
+    }
+}
+
+fn less_than(language: Language) -> &'static str {
+    match language {
+        Language::En => "Less than",
+        Language::Fr => "Moins de",
+        Language::Es => "Menos de",
+    }
+}
+
+// French and Spanish write decimals with a comma
+fn number(value: f64, language: Language) -> String {
+    let text = value.to_string();
+    match language {
+        Language::En => text,
+        Language::Fr | Language::Es => text.replace('.', ","),
+    }
+}
+
+// Converted values get one decimal place when small and none otherwise,
+// "0.6 to 1.5 metres" and "23 to 91 kilograms" rather than long fractions
+fn round_for_display(value: f64) -> f64 {
+    if value.abs() < 10.0 {
+        (value * 10.0).round() / 10.0
+    } else {
+        value.round()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(min: Option<f64>, max: f64, unit: Unit) -> Measurement {
+        Measurement { min, max, unit }
+    }
+
+    fn formats(m: Measurement, language: Language) -> [String; 3] {
+        [UnitSystem::Imperial, UnitSystem::Metric, UnitSystem::Both]
+            .map(|system| m.format(system, language))
+    }
+
+    #[test]
+    fn imperial_range_in_each_system() {
+        assert_eq!(
+            formats(range(Some(2.0), 5.0, Unit::Feet), Language::En),
+            [
+                "2 to 5 feet",
+                "0.6 to 1.5 metres",
+                "2 to 5 feet (0.6 to 1.5 metres)",
+            ]
+        );
+    }
+
+    #[test]
+    fn metric_range_in_each_system() {
+        assert_eq!(
+            formats(range(Some(10.0), 45.0, Unit::Kilograms), Language::En),
+            [
+                "22 to 99 pounds",
+                "10 to 45 kilograms",
+                "22 to 99 pounds (10 to 45 kilograms)",
+            ]
+        );
+        assert_eq!(
+            formats(range(None, 30.0, Unit::Centimetres), Language::En),
+            [
+                "Less than 12 inches",
+                "Less than 30 centimetres",
+                "Less than 12 inches (30 centimetres)",
+            ]
+        );
+    }
+
+    #[test]
+    fn years_in_each_system() {
+        assert_eq!(
+            formats(range(Some(5.0), 10.0, Unit::Years), Language::En),
+            ["5 to 10 years", "5 to 10 years", "5 to 10 years"]
+        );
+    }
+
+    #[test]
+    fn quantity_in_each_system() {
+        let formats = |q: Quantity, language| {
+            [UnitSystem::Imperial, UnitSystem::Metric, UnitSystem::Both]
+                .map(|system| q.format(system, language))
+        };
+        let pound = Quantity {
+            value: 1.0,
+            unit: Unit::Pounds,
+        };
+        assert_eq!(
+            formats(pound, Language::En),
+            ["1 pound", "0.5 kilograms", "1 pound (0.5 kilograms)"]
+        );
+        let metres = Quantity {
+            value: 2.0,
+            unit: Unit::Metres,
+        };
+        assert_eq!(
+            formats(metres, Language::Es),
+            ["6,6 pies", "2 metros", "6,6 pies (2 metros)"]
+        );
+    }
+
+    #[test]
+    fn french_singular_below_two() {
+        let half = Quantity {
+            value: 0.5,
+            unit: Unit::Kilograms,
+        };
+        assert_eq!(
+            half.format(UnitSystem::Metric, Language::Fr),
+            "0,5 kilogramme"
+        );
+        assert_eq!(half.format(UnitSystem::Imperial, Language::Fr), "1,1 livre");
+        assert_eq!(
+            half.format(UnitSystem::Imperial, Language::En),
+            "1.1 pounds"
+        );
+        let two = Quantity {
+            value: 2.0,
+            unit: Unit::Kilograms,
+        };
+        assert_eq!(
+            two.format(UnitSystem::Metric, Language::Fr),
+            "2 kilogrammes"
+        );
+    }
+}
@@ -0,0 +1,454 @@
+//! Minimal NDEF parsing for NTAG2xx memory dumps.
+//! Only what the fish tags need: finding the NDEF message TLV in the user
+//! memory area and splitting it into records, and the reverse for writing
+//! tags.
+
+// NTAG2xx user memory (and so the TLV area) starts at page 4
+pub const USER_MEMORY_OFFSET: usize = 16;
+
+// Capability container in page 3, byte 2 is the data area size / 8
+const CC_OFFSET: usize = 12;
+const CC_MAGIC: u8 = 0xE1;
+
+const TLV_NULL: u8 = 0x00;
+const TLV_NDEF_MESSAGE: u8 = 0x03;
+const TLV_TERMINATOR: u8 = 0xFE;
+
+const FLAG_MB: u8 = 0x80;
+const FLAG_ME: u8 = 0x40;
+const FLAG_SR: u8 = 0x10;
+const FLAG_IL: u8 = 0x08;
+const TNF_MASK: u8 = 0x07;
+
+pub const TNF_WELL_KNOWN: u8 = 0x01;
+pub const TNF_MIME: u8 = 0x02;
+pub const TNF_EXTERNAL: u8 = 0x04;
+const RTD_TEXT: &[u8] = b"T";
+const RTD_URI: &[u8] = b"U";
+
+// URI record identifier codes, the first payload byte abbreviates the start
+// of the URI (NFC Forum URI RTD, table 3). Index is the code.
+const URI_PREFIXES: [&str; 36] = [
+    "",
+    "http://www.",
+    "https://www.",
+    "http://",
+    "https://",
+    "tel:",
+    "mailto:",
+    "ftp://anonymous:anonymous@",
+    "ftp://ftp.",
+    "ftps://",
+    "sftp://",
+    "smb://",
+    "nfs://",
+    "ftp://",
+    "dav://",
+    "news:",
+    "telnet://",
+    "imap:",
+    "rtsp://",
+    "urn:",
+    "pop:",
+    "sip:",
+    "sips:",
+    "tftp:",
+    "btspp://",
+    "btl2cap://",
+    "btgoep://",
+    "tcpobex://",
+    "irdaobex://",
+    "file://",
+    "urn:epc:id:",
+    "urn:epc:tag:",
+    "urn:epc:pat:",
+    "urn:epc:raw:",
+    "urn:epc:",
+    "urn:nfc:",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub tnf: u8,
+    pub record_type: Vec<u8>,
+    pub id: Vec<u8>,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextRecord {
+    pub language: String,
+    pub text: String,
+}
+
+impl Record {
+    pub fn text(language: &str, text: &str) -> Self {
+        let mut payload = vec![language.len() as u8];
+        payload.extend_from_slice(language.as_bytes());
+        payload.extend_from_slice(text.as_bytes());
+        Self {
+            tnf: TNF_WELL_KNOWN,
+            record_type: RTD_TEXT.to_vec(),
+            id: Vec::new(),
+            payload,
+        }
+    }
+
+    /// URI record, abbreviated with the longest identifier code that fits
+    pub fn uri(uri: &str) -> Self {
+        let (code, prefix) = URI_PREFIXES
+            .iter()
+            .enumerate()
+            .filter(|(_, prefix)| uri.starts_with(*prefix))
+            .max_by_key(|(_, prefix)| prefix.len())
+            .unwrap_or((0, &""));
+
+        let mut payload = vec![code as u8];
+        payload.extend_from_slice(&uri.as_bytes()[prefix.len()..]);
+        Self {
+            tnf: TNF_WELL_KNOWN,
+            record_type: RTD_URI.to_vec(),
+            id: Vec::new(),
+            payload,
+        }
+    }
+
+    pub fn external(external_type: &str, payload: Vec<u8>) -> Self {
+        Self {
+            tnf: TNF_EXTERNAL,
+            record_type: external_type.as_bytes().to_vec(),
+            id: Vec::new(),
+            payload,
+        }
+    }
+
+    pub fn is_well_known(&self, record_type: &[u8]) -> bool {
+        self.tnf == TNF_WELL_KNOWN && self.record_type == record_type
+    }
+
+    /// MIME types are case-insensitive, "application/JSON" matches
+    pub fn is_mime(&self, mime_type: &str) -> bool {
+        self.tnf == TNF_MIME && self.record_type.eq_ignore_ascii_case(mime_type.as_bytes())
+    }
+
+    /// NFC Forum external types ("domain:type") are case-insensitive too
+    pub fn is_external(&self, external_type: &str) -> bool {
+        self.tnf == TNF_EXTERNAL
+            && self
+                .record_type
+                .eq_ignore_ascii_case(external_type.as_bytes())
+    }
+
+    /// Decodes an NFC Forum well-known URI record ("U") to the full URI.
+    /// Reserved identifier codes are treated as no prefix, as the spec says.
+    pub fn as_uri(&self) -> Option<String> {
+        if !self.is_well_known(RTD_URI) {
+            return None;
+        }
+
+        let (&code, rest) = self.payload.split_first()?;
+        let prefix = URI_PREFIXES.get(code as usize).unwrap_or(&"");
+        let rest = std::str::from_utf8(rest).ok()?;
+        Some(format!("{}{}", prefix, rest))
+    }
+
+    /// Decodes an NFC Forum well-known text record ("T")
+    pub fn as_text(&self) -> Option<TextRecord> {
+        if !self.is_well_known(RTD_TEXT) {
+            return None;
+        }
+
+        // status byte: bit 7 = UTF-16, bits 0-5 = language code length
+        let status = *self.payload.first()?;
+        if status & 0x80 != 0 {
+            return None;
+        }
+        let lang_len = (status & 0x3F) as usize;
+        let lang = self.payload.get(1..1 + lang_len)?;
+        let text = self.payload.get(1 + lang_len..)?;
+
+        Some(TextRecord {
+            language: String::from_utf8(lang.to_vec()).ok()?,
+            text: String::from_utf8(text.to_vec()).ok()?,
+        })
+    }
+}
+
+/// Finds the NDEF message TLV in a tag memory dump starting at page 0, and
+/// parses the records in it. Returns `None` if there's no NDEF message or
+/// it's truncated or malformed.
+pub fn parse_tag_memory(memory: &[u8]) -> Option<Vec<Record>> {
+    let mut pos = USER_MEMORY_OFFSET;
+
+    while pos < memory.len() {
+        let tag = memory[pos];
+        match tag {
+            TLV_NULL => {
+                pos += 1;
+                continue;
+            }
+            TLV_TERMINATOR => return None,
+            _ => {}
+        }
+
+        let (len, header_len) = match *memory.get(pos + 1)? {
+            0xFF => {
+                let hi = *memory.get(pos + 2)? as usize;
+                let lo = *memory.get(pos + 3)? as usize;
+                ((hi << 8) | lo, 4)
+            }
+            len => (len as usize, 2),
+        };
+        let value = memory.get(pos + header_len..pos + header_len + len)?;
+
+        if tag == TLV_NDEF_MESSAGE {
+            return parse_message(value);
+        }
+        // lock control, memory control and proprietary TLVs are skipped
+        pos += header_len + len;
+    }
+
+    None
+}
+
+pub fn parse_message(mut data: &[u8]) -> Option<Vec<Record>> {
+    let mut records = Vec::new();
+
+    while !data.is_empty() {
+        let header = data[0];
+        let type_len = *data.get(1)? as usize;
+        let mut pos = 2;
+
+        let payload_len = if header & FLAG_SR != 0 {
+            let len = *data.get(pos)? as usize;
+            pos += 1;
+            len
+        } else {
+            let bytes = data.get(pos..pos + 4)?;
+            pos += 4;
+            u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+        };
+
+        let id_len = if header & FLAG_IL != 0 {
+            let len = *data.get(pos)? as usize;
+            pos += 1;
+            len
+        } else {
+            0
+        };
+
+        let record_type = data.get(pos..pos + type_len)?.to_vec();
+        pos += type_len;
+        let id = data.get(pos..pos + id_len)?.to_vec();
+        pos += id_len;
+        let payload = data.get(pos..pos.checked_add(payload_len)?)?.to_vec();
+        pos += payload_len;
+
+        records.push(Record {
+            tnf: header & TNF_MASK,
+            record_type,
+            id,
+            payload,
+        });
+        if header & FLAG_ME != 0 {
+            break;
+        }
+        data = &data[pos..];
+    }
+
+    Some(records)
+}
+
+/// Size in bytes of the tag's user memory according to its capability
+/// container, e.g. 144 for NTAG213, 496 for NTAG215 and 872 for NTAG216.
+pub fn data_area_size(memory: &[u8]) -> Option<usize> {
+    let cc = memory.get(CC_OFFSET..CC_OFFSET + 4)?;
+    (cc[0] == CC_MAGIC).then_some(cc[2] as usize * 8)
+}
+
+pub fn encode_message(records: &[Record]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    for (i, record) in records.iter().enumerate() {
+        let mut header = record.tnf & TNF_MASK;
+        if i == 0 {
+            header |= FLAG_MB;
+        }
+        if i == records.len() - 1 {
+            header |= FLAG_ME;
+        }
+        let short = record.payload.len() <= u8::MAX as usize;
+        if short {
+            header |= FLAG_SR;
+        }
+        if !record.id.is_empty() {
+            header |= FLAG_IL;
+        }
+
+        out.push(header);
+        out.push(record.record_type.len() as u8);
+        if short {
+            out.push(record.payload.len() as u8);
+        } else {
+            out.extend_from_slice(&(record.payload.len() as u32).to_be_bytes());
+        }
+        if !record.id.is_empty() {
+            out.push(record.id.len() as u8);
+        }
+        out.extend_from_slice(&record.record_type);
+        out.extend_from_slice(&record.id);
+        out.extend_from_slice(&record.payload);
+    }
+
+    out
+}
+
+/// The bytes to write from page 4 onwards for a tag holding just `records`,
+/// padded to whole pages. Errors if it won't fit in `capacity` bytes of user
+/// memory.
+pub fn encode_tag_memory(records: &[Record], capacity: usize) -> Result<Vec<u8>, String> {
+    let message = encode_message(records);
+
+    let mut out = vec![TLV_NDEF_MESSAGE];
+    if message.len() < 0xFF {
+        out.push(message.len() as u8);
+    } else {
+        out.push(0xFF);
+        out.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    }
+    out.extend_from_slice(&message);
+    out.push(TLV_TERMINATOR);
+
+    if out.len() > capacity {
+        return Err(format!(
+            "NDEF message needs {} bytes, tag only has {}",
+            out.len(),
+            capacity
+        ));
+    }
+
+    out.resize(out.len().div_ceil(4) * 4, TLV_NULL);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NTAG213 pages 0-3, then `user_memory` from page 4
+    fn tag(user_memory: &[u8]) -> Vec<u8> {
+        let mut memory = vec![
+            0x04, 0xA1, 0xB2, 0x9F, 0xC3, 0xD4, 0xE5, 0xF6, 0x04, 0x48, 0x00, 0x00, 0xE1, 0x10,
+            0x12, 0x00,
+        ];
+        memory.extend_from_slice(user_memory);
+        memory
+    }
+
+    #[test]
+    fn uri_prefix_codes() {
+        let cases = [
+            (
+                "https://sno-port.example/fish/3",
+                0x04,
+                "sno-port.example/fish/3",
+            ),
+            ("https://www.example.org/", 0x02, "example.org/"),
+            ("http://example.org", 0x03, "example.org"),
+            ("tel:+15555550123", 0x05, "+15555550123"),
+            ("urn:nfc:sn:1234", 0x23, "sn:1234"),
+            // nothing abbreviates these
+            ("geo:70.5,-150.2", 0x00, "geo:70.5,-150.2"),
+            ("", 0x00, ""),
+        ];
+        for (uri, code, rest) in cases {
+            let record = Record::uri(uri);
+            assert_eq!(record.payload[0], code, "{}", uri);
+            assert_eq!(&record.payload[1..], rest.as_bytes(), "{}", uri);
+            assert_eq!(record.as_uri().as_deref(), Some(uri));
+        }
+    }
+
+    #[test]
+    fn unknown_uri_prefix_code() {
+        let mut record = Record::uri("sno-port.example/fish/3");
+        for code in [0x24, 0x7F, 0xFF] {
+            record.payload[0] = code;
+            assert_eq!(
+                record.as_uri().as_deref(),
+                Some("sno-port.example/fish/3"),
+                "code {}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let records = vec![
+            Record::text("fr", "3"),
+            Record::uri("https://sno-port.example/fish/3"),
+            Record::external("sno-port.example:catch", b"{}".to_vec()),
+        ];
+        let memory = tag(&encode_tag_memory(&records, 144).unwrap());
+        let parsed = parse_tag_memory(&memory).unwrap();
+        assert_eq!(parsed, records);
+        assert_eq!(
+            parsed[0].as_text(),
+            Some(TextRecord {
+                language: "fr".to_string(),
+                text: "3".to_string()
+            })
+        );
+        assert!(parsed[2].is_external("SNO-PORT.example:Catch"));
+    }
+
+    #[test]
+    fn one_byte_tlv_length() {
+        // text record "3", language "en", after a lock control TLV and NULLs
+        let memory = tag(&[
+            0x01, 0x03, 0xA0, 0x10, 0x44, 0x00, 0x00, 0x03, 0x08, 0xD1, 0x01, 0x04, 0x54, 0x02,
+            0x65, 0x6E, 0x33, 0xFE,
+        ]);
+        let records = parse_tag_memory(&memory).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].as_text().unwrap().text, "3");
+    }
+
+    #[test]
+    fn three_byte_tlv_length() {
+        // a long payload needs a long record and a 3 byte TLV length
+        let records = vec![Record::external("sno-port.example:fish", vec![0x5A; 400])];
+        let user_memory = encode_tag_memory(&records, 496).unwrap();
+        assert_eq!(user_memory[..2], [TLV_NDEF_MESSAGE, 0xFF]);
+        let len = u16::from_be_bytes([user_memory[2], user_memory[3]]) as usize;
+        assert_eq!(len, encode_message(&records).len());
+        assert_eq!(parse_tag_memory(&tag(&user_memory)), Some(records));
+    }
+
+    #[test]
+    fn truncated_memory() {
+        let records = vec![Record::uri("https://sno-port.example/fish/3")];
+        let memory = tag(&encode_tag_memory(&records, 144).unwrap());
+        let message_end = USER_MEMORY_OFFSET + 2 + encode_message(&records).len();
+        for len in 0..message_end {
+            assert_eq!(parse_tag_memory(&memory[..len]), None, "cut at {}", len);
+        }
+        assert_eq!(parse_tag_memory(&memory[..message_end]), Some(records));
+
+        // a 3 byte length cut off in the middle
+        assert_eq!(parse_tag_memory(&tag(&[0x03, 0xFF, 0x01])), None);
+    }
+
+    #[test]
+    fn no_ndef_message() {
+        assert_eq!(parse_tag_memory(&tag(&[0x00; 16])), None);
+        assert_eq!(parse_tag_memory(&tag(&[0xFE, 0x03, 0x00])), None);
+    }
+
+    #[test]
+    fn too_big_for_the_tag() {
+        let records = vec![Record::external("sno-port.example:fish", vec![0; 200])];
+        assert!(encode_tag_memory(&records, 144).is_err());
+    }
+}
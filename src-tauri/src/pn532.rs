@@ -3,15 +3,15 @@
 //! Local copy at time of writing saved to this repo at './pnc_hat_docs.pdf',
 //! the same page can be located at: https://www.waveshare.com/wiki/PN532_NFC_HAT
 
-use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
-use spidev::{SpiModeFlags, Spidev, SpidevOptions, SpidevTransfer};
+use std::fmt;
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
-const PN532_PREAMBLE: u8 = 0x00;
-const PN532_STARTCODE1: u8 = 0x00;
-const PN532_STARTCODE2: u8 = 0xFF;
-const PN532_POSTAMBLE: u8 = 0x00;
+use crate::frame::{self, Frame};
+use crate::ndef;
+use crate::spi::Transport;
+use crate::wear::ReadStats;
+
 const PN532_HOSTTOPN532: u8 = 0xD4;
 const PN532_PN532TOHOST: u8 = 0xD5;
 
@@ -19,123 +19,146 @@ const CMD_SAMCONFIGURATION: u8 = 0x14;
 const CMD_INLISTPASSIVETARGET: u8 = 0x4A;
 const CMD_INDATAEXCHANGE: u8 = 0x40;
 
+// the tag InListPassiveTarget selected, it's only ever asked for one
+const TARGET: u8 = 0x01;
+// error code in the low 6 bits of an InDataExchange status byte, the top
+// two are the MI and NAD flags
+const STATUS_ERROR_MASK: u8 = 0x3F;
+
 const NTAG_CMD_READ: u8 = 0x30;
+const NTAG_CMD_WRITE: u8 = 0xA2;
+const NTAG_CMD_READ_SIG: u8 = 0x3C;
+const NTAG_CMD_PWD_AUTH: u8 = 0x1B;
+const NTAG_CMD_READ_CNT: u8 = 0x39;
+// the NFC counter is counter 2, the only one NTAG21x has
+const NTAG_NFC_COUNTER: u8 = 0x02;
+
+// NTAG21x configuration pages, relative to CFG0: CFG0 (AUTH0 in byte 3),
+// CFG1 (ACCESS in byte 0), PWD, PACK
+const CFG1_OFFSET: u8 = 1;
+const PWD_OFFSET: u8 = 2;
+const PACK_OFFSET: u8 = 3;
+// ACCESS bit 7, set = the password protects reads as well as writes
+const ACCESS_PROT: u8 = 0x80;
+// ACCESS bit 4, the NFC counter counts reads and READ_CNT works
+const ACCESS_NFC_CNT_EN: u8 = 0x10;
+
+// static lock bytes are bytes 2 and 3 of page 2, the capability container
+// is page 3
+const STATIC_LOCK_PAGE: u8 = 2;
+const CC_PAGE: u8 = 3;
+// lock pages 3-7 and 8-15, leaving the block-locking bits clear
+const STATIC_LOCK_BITS: [u8; 2] = [0xF8, 0xFF];
+// CC byte 3, NDEF write access "no access", so phones show the tag read-only
+const CC_READ_ONLY: u8 = 0x0F;
+
+// NTAG213 user memory, for tags whose capability container can't be read
+const NTAG213_DATA_AREA: usize = 144;
+
+// times to try a block read again, after the tag's answer kept getting
+// garbled, before giving up on the rest of the tag
+const READ_RETRIES: u32 = 2;
+// times to send a command again when the PN532 didn't get it, and to NACK
+// for a garbled response
+const LINK_RETRIES: u32 = 2;
+// times to send a command to the tag again when its answer got garbled on
+// the air
+const TAG_RETRIES: u32 = 2;
 
 const SPI_DATAWRITE: u8 = 0x01;
-const SPI_STATREAD: u8 = 0x02;
+pub const SPI_STATREAD: u8 = 0x02;
 const SPI_DATAREAD: u8 = 0x03;
 const SPI_READY: u8 = 0x01;
 
-const ACK: [u8; 6] = [0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00];
-
-// Waveshare PN532 NFC HAT pinout:
-//   RSTPDN → BCM 20
-//   NSS    → BCM 4 (directly to D4 (BCM), not directly to the Pi's dedicated SPI0 CE0 pin)
-const RESET_PIN: u32 = 20;
-const NSS_PIN: u32 = 4;
-
-// PN532 SPI uses LSB-first bit order, but Linux SPI is MSB-first
-// so we need to reverse the bits before and after sending/receiving data
-fn reverse_bit(b: u8) -> u8 {
-    let mut result: u8 = 0;
-    let mut num = b;
-    for _ in 0..8 {
-        result <<= 1;
-        result += num & 1;
-        num >>= 1;
-    }
-    result
+// from the capability container in the first 4 pages
+fn user_memory_size(header: &[u8]) -> usize {
+    ndef::data_area_size(header).unwrap_or(NTAG213_DATA_AREA)
 }
 
-pub struct Pn532 {
-    spi: Spidev,
-    cs: LineHandle,
-    reset: LineHandle,
+/// What went wrong talking to the PN532, or through it to a tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pn532Error {
+    /// Not ready in time, for InListPassiveTarget usually just no tag
+    Timeout,
+    /// The command wasn't acknowledged
+    NoAck,
+    /// The PN532 couldn't make sense of the command frame
+    ErrorFrame,
+    /// The response frame couldn't be read, even after asking again
+    Frame(frame::FrameError),
+    /// A response for some other command, or too short
+    UnexpectedResponse,
+    /// Error code from an InDataExchange status byte (user manual section 7.1)
+    Status(u8),
 }
 
-impl Pn532 {
-    pub fn new() -> Result<Self, String> {
-        let mut spi =
-            Spidev::open("/dev/spidev0.0").map_err(|e| format!("Failed to open SPI: {}", e))?;
-
-        spi.configure(
-            &SpidevOptions::new()
-                .bits_per_word(8)
-                .max_speed_hz(1_000_000)
-                .mode(SpiModeFlags::SPI_MODE_0)
-                .build(),
-        )
-        .map_err(|e| format!("Failed to configure SPI: {}", e))?;
-
-        let mut chip =
-            Chip::new("/dev/gpiochip0").map_err(|e| format!("Failed to open GPIO: {}", e))?;
-
-        let cs = chip
-            .get_line(NSS_PIN)
-            .map_err(|e| format!("Failed to get NSS pin: {}", e))?
-            .request(LineRequestFlags::OUTPUT, 1, "pn532-cs")
-            .map_err(|e| format!("Failed to request CS: {}", e))?;
-
-        let reset = chip
-            .get_line(RESET_PIN)
-            .map_err(|e| format!("Failed to get reset pin: {}", e))?
-            .request(LineRequestFlags::OUTPUT, 1, "pn532-reset")
-            .map_err(|e| format!("Failed to request reset: {}", e))?;
-
-        let mut pn532 = Self { spi, cs, reset };
-        pn532.hardware_reset();
-        pn532.wakeup();
-        Ok(pn532)
-    }
-
-    fn hardware_reset(&mut self) {
-        self.reset.set_value(1).ok();
-        sleep(Duration::from_millis(100));
-        self.reset.set_value(0).ok();
-        sleep(Duration::from_millis(500));
-        self.reset.set_value(1).ok();
-        sleep(Duration::from_millis(100));
-    }
-
-    fn cs_low(&self) {
-        self.cs.set_value(0).ok();
-    }
-
-    fn cs_high(&self) {
-        self.cs.set_value(1).ok();
+impl Pn532Error {
+    // Errors that might well not happen again, where the tag's answer was
+    // garbled on the air rather than refused. Timeouts aren't, the tag has
+    // gone or has halted after something it didn't like.
+    fn is_transient(self) -> bool {
+        matches!(self, Pn532Error::Status(0x02..=0x06))
     }
+}
 
-    fn spi_rw(&mut self, data: &mut [u8]) {
-        self.cs_low();
-        sleep(Duration::from_millis(1));
-
-        for b in data.iter_mut() {
-            *b = reverse_bit(*b);
+impl fmt::Display for Pn532Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pn532Error::Timeout => f.write_str("PN532 didn't respond in time"),
+            Pn532Error::NoAck => f.write_str("PN532 didn't acknowledge the command"),
+            Pn532Error::ErrorFrame => f.write_str("PN532 couldn't read the command"),
+            Pn532Error::Frame(e) => write!(f, "Bad response from the PN532, {}", e),
+            Pn532Error::UnexpectedResponse => f.write_str("Unexpected response from the PN532"),
+            Pn532Error::Status(code) => {
+                let message = match code {
+                    0x01 => "Tag didn't answer",
+                    0x02 => "CRC error from the tag",
+                    0x03 => "Parity error from the tag",
+                    0x04 => "Wrong bit count from the tag",
+                    0x05 => "Framing error from the tag",
+                    0x06 => "Collision, more than one tag in the field",
+                    0x07 => "PN532 buffer too small",
+                    0x09 => "RF buffer overflow",
+                    0x0A => "RF field not switched on in time",
+                    0x0B => "RF protocol error",
+                    0x0D => "PN532 is overheating",
+                    0x0E => "PN532 internal buffer overflow",
+                    0x10 => "Invalid parameter",
+                    0x12 => "Command not supported by the tag",
+                    0x13 => "Data format doesn't match the spec",
+                    0x14 => "Authentication error",
+                    0x23 => "Wrong UID check byte",
+                    0x25 => "Invalid device state",
+                    0x26 => "Operation not allowed in this configuration",
+                    0x27 => "Command not acceptable in the current context",
+                    0x29 => "Tag released",
+                    0x2A => "Card ID doesn't match",
+                    0x2B => "Tag has gone",
+                    0x2D => "Over-current",
+                    _ => return write!(f, "PN532 error 0x{:02X}", code),
+                };
+                f.write_str(message)
+            }
         }
+    }
+}
 
-        let tx = data.to_vec();
-        let mut transfer = SpidevTransfer::read_write(&tx, data);
-        self.spi.transfer(&mut transfer).ok();
+pub struct Pn532 {
+    transport: Box<dyn Transport>,
+    stats: ReadStats,
+}
 
-        for b in data.iter_mut() {
-            *b = reverse_bit(*b);
+impl Pn532 {
+    pub fn new(mut transport: Box<dyn Transport>) -> Self {
+        transport.power_on();
+        Self {
+            transport,
+            stats: ReadStats::default(),
         }
-
-        sleep(Duration::from_millis(1));
-        self.cs_high();
     }
 
-    fn wakeup(&mut self) {
-        sleep(Duration::from_millis(1000));
-        self.cs_low();
-        sleep(Duration::from_millis(2));
-        let mut data = [0x00u8];
-        data[0] = reverse_bit(data[0]);
-        let tx = data.to_vec();
-        let mut transfer = SpidevTransfer::read_write(&tx, &mut data);
-        self.spi.transfer(&mut transfer).ok();
-        sleep(Duration::from_millis(1000));
-        self.cs_high();
+    fn spi_rw(&mut self, data: &mut [u8]) {
+        self.transport.transfer(data);
     }
 
     fn read_data(&mut self, count: usize) -> Vec<u8> {
@@ -167,163 +190,631 @@ impl Pn532 {
         false
     }
 
-    fn write_frame(&mut self, data: &[u8]) {
-        let len = data.len() as u8;
-        let lcs = (!len).wrapping_add(1);
-        let checksum: u8 = data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
-        let dcs = (!checksum).wrapping_add(1);
-
-        // [PREAMBLE 00 FF] [LEN LCS] [DATA...] [DCS POSTAMBLE]
-        let mut frame = vec![PN532_PREAMBLE, PN532_STARTCODE1, PN532_STARTCODE2, len, lcs];
-        frame.extend_from_slice(data);
-        frame.push(dcs);
-        frame.push(PN532_POSTAMBLE);
-
-        self.write_data(&frame);
-    }
-
-    fn read_frame(&mut self, length: usize) -> Option<Vec<u8>> {
-        let buf = self.read_data(length + 7);
-
-        let mut offset = 0;
-        while offset < buf.len() && buf[offset] == 0x00 {
-            offset += 1;
-        }
-        if offset >= buf.len() || buf[offset] != 0xFF {
-            return None;
-        }
-        offset += 1;
-
-        if offset >= buf.len() {
-            return None;
-        }
-
-        let frame_len = buf[offset] as usize;
-        if ((frame_len as u8).wrapping_add(buf[offset + 1])) != 0 {
-            return None;
-        }
-
-        let checksum: u8 = buf[offset + 2..offset + 2 + frame_len + 1]
-            .iter()
-            .fold(0u8, |acc, &b| acc.wrapping_add(b));
-        if checksum != 0 {
-            return None;
-        }
-
-        Some(buf[offset + 2..offset + 2 + frame_len].to_vec())
+    fn read_frame(&mut self, length: usize) -> Result<Frame, frame::FrameError> {
+        let buf = self.read_data(frame::encoded_len(length));
+        frame::decode(&buf)
     }
 
     fn read_ack(&mut self) -> bool {
-        let buf = self.read_data(6);
-        buf == ACK
+        let buf = self.read_data(frame::ACK.len());
+        frame::decode(&buf) == Ok(Frame::Ack)
     }
 
+    // Sends a command and returns the response data after the command code,
+    // sending it again if the PN532 didn't get it
     fn call(
         &mut self,
         cmd: u8,
         params: &[u8],
         response_len: usize,
         timeout_ms: u64,
-    ) -> Option<Vec<u8>> {
+    ) -> Result<Vec<u8>, Pn532Error> {
         let mut data = vec![PN532_HOSTTOPN532, cmd];
         data.extend_from_slice(params);
+        let command = frame::encode(&data).map_err(Pn532Error::Frame)?;
+
+        let mut attempt = 0;
+        let resp = loop {
+            match self.exchange(&command, response_len + 2, timeout_ms) {
+                Err(Pn532Error::NoAck | Pn532Error::ErrorFrame) if attempt < LINK_RETRIES => {
+                    attempt += 1;
+                }
+                result => break result?,
+            }
+        };
+
+        if resp.len() < 2 || resp[0] != PN532_PN532TOHOST || resp[1] != cmd.wrapping_add(1) {
+            return Err(Pn532Error::UnexpectedResponse);
+        }
+
+        Ok(resp[2..].to_vec())
+    }
 
-        self.write_frame(&data);
+    // One go at a command: write it, wait for the ACK, then the response
+    fn exchange(
+        &mut self,
+        command: &[u8],
+        response_len: usize,
+        timeout_ms: u64,
+    ) -> Result<Vec<u8>, Pn532Error> {
+        self.write_data(command);
 
         if !self.wait_ready(timeout_ms) {
-            return None;
+            return Err(Pn532Error::Timeout);
         }
 
         if !self.read_ack() {
-            return None;
+            return Err(Pn532Error::NoAck);
         }
 
         if !self.wait_ready(timeout_ms) {
-            return None;
+            return Err(Pn532Error::Timeout);
         }
 
-        let resp = self.read_frame(response_len + 2)?;
+        // a NACK has the PN532 send the same response again
+        let mut attempt = 0;
+        loop {
+            match self.read_frame(response_len) {
+                Ok(Frame::Data(data)) => return Ok(data),
+                Ok(Frame::Error) => return Err(Pn532Error::ErrorFrame),
+                Ok(Frame::Ack | Frame::Nack) => return Err(Pn532Error::UnexpectedResponse),
+                Err(e) if e.is_checksum() => {
+                    self.stats.checksum_errors += 1;
+                    if attempt == LINK_RETRIES {
+                        return Err(Pn532Error::Frame(e));
+                    }
+                }
+                Err(e) => return Err(Pn532Error::Frame(e)),
+            }
 
-        if resp.len() < 2 || resp[0] != PN532_PN532TOHOST || resp[1] != cmd + 1 {
-            return None;
+            attempt += 1;
+            self.write_data(&frame::NACK);
+            if !self.wait_ready(timeout_ms) {
+                return Err(Pn532Error::Timeout);
+            }
         }
+    }
 
-        Some(resp[2..].to_vec())
+    // Sends `tag_cmd` to the tag and returns its answer, going again when
+    // the answer was garbled
+    fn data_exchange(
+        &mut self,
+        tag_cmd: &[u8],
+        response_len: usize,
+    ) -> Result<Vec<u8>, Pn532Error> {
+        let mut params = vec![TARGET];
+        params.extend_from_slice(tag_cmd);
+
+        let mut attempt = 0;
+        loop {
+            let result = self
+                .call(CMD_INDATAEXCHANGE, &params, response_len + 1, 500)
+                .and_then(|resp| {
+                    let (&status, data) =
+                        resp.split_first().ok_or(Pn532Error::UnexpectedResponse)?;
+                    match status & STATUS_ERROR_MASK {
+                        0x00 => Ok(data.to_vec()),
+                        code => Err(Pn532Error::Status(code)),
+                    }
+                });
+            match result {
+                Err(e) if e.is_transient() && attempt < TAG_RETRIES => {
+                    attempt += 1;
+                    self.stats.retries += 1;
+                }
+                result => return result,
+            }
+        }
     }
 
     pub fn sam_config(&mut self) -> bool {
         // Normal mode (0x01), timeout 1s (0x14), use IRQ (0x01)
         self.call(CMD_SAMCONFIGURATION, &[0x01, 0x14, 0x01], 0, 1000)
-            .is_some()
+            .is_ok()
     }
 
-    pub fn read_passive_target(&mut self, timeout_ms: u64) -> Option<Vec<u8>> {
+    /// UID of the tag in the field, `Ok(None)` if none turned up in time.
+    /// `Err` only when the PN532 itself misbehaved.
+    pub fn read_passive_target(&mut self, timeout_ms: u64) -> Result<Option<Vec<u8>>, Pn532Error> {
         const RESPONSE_LENGTH: usize = 19;
 
         // Baud 106 kbps / Type A ISO14443A
-        let resp = self.call(
+        let resp = match self.call(
             CMD_INLISTPASSIVETARGET,
             &[0x01, 0x00], // MaxTg=0x01, BrTy=0x00
             RESPONSE_LENGTH,
             timeout_ms,
-        )?;
+        ) {
+            Ok(resp) => resp,
+            // no tag
+            Err(Pn532Error::Timeout) => return Ok(None),
+            Err(e) => return Err(e),
+        };
 
         if resp.is_empty() || resp[0] != 0x01 {
-            return None;
+            return Ok(None);
         }
 
         if resp.len() > 5 {
             let uid_len = resp[5] as usize;
             if resp.len() >= 6 + uid_len {
-                return Some(resp[6..6 + uid_len].to_vec());
+                return Ok(Some(resp[6..6 + uid_len].to_vec()));
             }
         }
-        None
+        Ok(None)
     }
 
     // read an NTAG2xx block (4 bytes per page, returns 16 bytes = 4 pages)
-    pub fn ntag_read_block(&mut self, page: u8) -> Option<Vec<u8>> {
-        let params = [0x01, NTAG_CMD_READ, page];
-        let resp = self.call(CMD_INDATAEXCHANGE, &params, 17, 500)?;
+    pub fn ntag_read_block(&mut self, page: u8) -> Result<Vec<u8>, Pn532Error> {
+        let data = self.data_exchange(&[NTAG_CMD_READ, page], 16)?;
+        // a NAK from the tag is a single byte
+        if data.len() < 16 {
+            return Err(Pn532Error::UnexpectedResponse);
+        }
+        Ok(data)
+    }
+
+    fn ntag_read_block_with_retries(&mut self, page: u8) -> Result<Vec<u8>, Pn532Error> {
+        let mut attempt = 0;
+        loop {
+            match self.ntag_read_block(page) {
+                Err(e) if e.is_transient() && attempt < READ_RETRIES => {
+                    attempt += 1;
+                    self.stats.retries += 1;
+                }
+                Err(e) => {
+                    self.stats.failures += 1;
+                    return Err(e);
+                }
+                ok => return ok,
+            }
+        }
+    }
+
+    /// What's gone wrong reading tags since the last call
+    pub fn take_stats(&mut self) -> ReadStats {
+        std::mem::take(&mut self.stats)
+    }
 
-        if resp.is_empty() || resp[0] != 0x00 {
+    /// The NTAG NFC counter, how many times the tag has been read by anything
+    /// since it was turned on. `None` if it isn't turned on, READ_CNT is only
+    /// sent when it is since a refused command halts the tag.
+    pub fn ntag_read_counter(&mut self, config_page: u8) -> Option<u32> {
+        let block = self.ntag_read_block(config_page).ok()?;
+        if block[4] & ACCESS_NFC_CNT_EN == 0 {
             return None;
         }
 
-        Some(resp[1..].to_vec())
+        let resp = self
+            .data_exchange(&[NTAG_CMD_READ_CNT, NTAG_NFC_COUNTER], 3)
+            .ok()?;
+        if resp.len() < 3 {
+            return None;
+        }
+        // 24 bit, least significant byte first
+        Some(u32::from_le_bytes([resp[0], resp[1], resp[2], 0]))
+    }
+
+    /// Turns on the NTAG NFC counter, if the tag is protected it has to be
+    /// authenticated first
+    pub fn ntag_enable_counter(&mut self, config_page: u8) -> Result<(), String> {
+        let block = self
+            .ntag_read_block(config_page)
+            .map_err(|e| format!("Failed to read config pages - {}", e))?;
+        if block[4] & ACCESS_NFC_CNT_EN != 0 {
+            return Ok(());
+        }
+
+        let cfg1 = [block[4] | ACCESS_NFC_CNT_EN, block[5], block[6], block[7]];
+        let page = config_page + CFG1_OFFSET;
+        self.ntag_write_page(page, cfg1)
+            .map_err(|e| format!("Failed to write config page {} - {}", page, e))
+    }
+
+    // NXP originality signature, 32 bytes of ECC signature over the UID
+    pub fn read_signature(&mut self) -> Option<[u8; 32]> {
+        let resp = self.data_exchange(&[NTAG_CMD_READ_SIG, 0x00], 32).ok()?;
+        resp.get(..32)?.try_into().ok()
+    }
+
+    /// Authenticates with the tag's password so protected pages can be
+    /// written, returns the tag's PACK. A wrong password halts the tag, it has
+    /// to be selected again before anything else works.
+    pub fn ntag_pwd_auth(&mut self, password: [u8; 4]) -> Option<[u8; 2]> {
+        let tag_cmd = [
+            NTAG_CMD_PWD_AUTH,
+            password[0],
+            password[1],
+            password[2],
+            password[3],
+        ];
+        let resp = self.data_exchange(&tag_cmd, 2).ok()?;
+        Some([*resp.first()?, *resp.get(1)?])
+    }
+
+    /// AUTH0, the first page that needs the password. Anything past the
+    /// config pages (0xFF by default) means the tag isn't protected.
+    pub fn ntag_auth0(&mut self, config_page: u8) -> Option<u8> {
+        let block = self.ntag_read_block(config_page).ok()?;
+        Some(block[3])
+    }
+
+    /// Sets the tag's password and PACK, then protects writes to `auth0` and
+    /// everything after it. Reads stay open. If the tag is already protected
+    /// it has to be authenticated first.
+    pub fn ntag_set_password(
+        &mut self,
+        config_page: u8,
+        password: [u8; 4],
+        pack: [u8; 2],
+        auth0: u8,
+    ) -> Result<(), String> {
+        let block = self
+            .ntag_read_block(config_page)
+            .map_err(|e| format!("Failed to read config pages - {}", e))?;
+        let mut cfg0 = [block[0], block[1], block[2], block[3]];
+        let mut cfg1 = [block[4], block[5], block[6], block[7]];
+        cfg0[3] = auth0;
+        cfg1[0] &= !ACCESS_PROT;
+
+        // AUTH0 last, the password has to be in place before it kicks in
+        let writes = [
+            (config_page + PWD_OFFSET, password),
+            (config_page + PACK_OFFSET, [pack[0], pack[1], 0x00, 0x00]),
+            (config_page + CFG1_OFFSET, cfg1),
+            (config_page, cfg0),
+        ];
+        for (page, data) in writes {
+            self.ntag_write_page(page, data)
+                .map_err(|e| format!("Failed to write config page {} - {}", page, e))?;
+        }
+        Ok(())
+    }
+
+    /// Permanently locks the capability container and user memory. Lock bits
+    /// can only ever be set, so there's no undoing this. `dynamic_lock_bits`
+    /// cover pages 16 on and differ between NTAG213/215/216.
+    pub fn ntag_lock(
+        &mut self,
+        dynamic_lock_page: u8,
+        dynamic_lock_bits: [u8; 3],
+    ) -> Result<(), String> {
+        // the CC, bytes 0 and 1 of page 2 and the lock bytes are one-time
+        // programmable, writes are ORed in so zeros leave them alone
+        let writes = [
+            (CC_PAGE, [0x00, 0x00, 0x00, CC_READ_ONLY]),
+            (
+                dynamic_lock_page,
+                [
+                    dynamic_lock_bits[0],
+                    dynamic_lock_bits[1],
+                    dynamic_lock_bits[2],
+                    0x00,
+                ],
+            ),
+            (
+                STATIC_LOCK_PAGE,
+                [0x00, 0x00, STATIC_LOCK_BITS[0], STATIC_LOCK_BITS[1]],
+            ),
+        ];
+        for (page, data) in writes {
+            self.ntag_write_page(page, data)
+                .map_err(|e| format!("Failed to write lock page {} - {}", page, e))?;
+        }
+        Ok(())
+    }
+
+    /// Whether the static lock bits for the user memory in pages 4-15 are set
+    pub fn ntag_is_locked(&mut self) -> Option<bool> {
+        let header = self.ntag_read_block(0).ok()?;
+        let start = STATIC_LOCK_PAGE as usize * 4;
+        let page = header.get(start..start + 4)?;
+        Some(page[2] & 0xF0 != 0 || page[3] != 0)
     }
 
-    pub fn read_fish_id(&mut self) -> Option<u32> {
-        let mut all_data = Vec::new();
+    // write one 4 byte NTAG2xx page
+    pub fn ntag_write_page(&mut self, page: u8, data: [u8; 4]) -> Result<(), Pn532Error> {
+        let tag_cmd = [NTAG_CMD_WRITE, page, data[0], data[1], data[2], data[3]];
+        self.data_exchange(&tag_cmd, 0).map(|_| ())
+    }
 
-        // read returns 16 bytes, we are using NTAG213 so 45 pages (0-44)
-        for page in (0..45).step_by(4) {
-            if let Some(data) = self.ntag_read_block(page as u8) {
+    // dump the tag memory, stopping early if a read fails. The capability
+    // container (page 3) says how much user memory there is, so this covers
+    // NTAG213/215/216 alike
+    pub fn read_memory(&mut self) -> Result<Vec<u8>, Pn532Error> {
+        let mut all_data = self.ntag_read_block_with_retries(0)?;
+        let data_area = user_memory_size(&all_data);
+        let end_page = (ndef::USER_MEMORY_OFFSET + data_area) / 4;
+
+        // read returns 16 bytes = 4 pages
+        for page in (4..end_page).step_by(4) {
+            if let Ok(data) = self.ntag_read_block_with_retries(page as u8) {
                 all_data.extend_from_slice(&data);
             } else {
                 break;
             }
         }
 
-        // for NDEF text records the first 2 bytes are "en"
-        // look for "en" followed by digits to find fish id entries
-        if let Some(pos) = all_data.windows(2).position(|w| w == b"en") {
-            let start = pos + 2;
-            if start < all_data.len() {
-                let mut num_str = String::new();
-                for &b in &all_data[start..] {
-                    if b.is_ascii_digit() {
-                        num_str.push(b as char);
-                    } else {
-                        break;
-                    }
-                }
-                if !num_str.is_empty() {
-                    return num_str.parse().ok();
+        // the last read can run on into the config pages
+        all_data.truncate(ndef::USER_MEMORY_OFFSET + data_area);
+        Ok(all_data)
+    }
+
+    /// Size of the user memory of the tag in the field, in bytes
+    pub fn user_memory_size(&mut self) -> Option<usize> {
+        let header = self.ntag_read_block(0).ok()?;
+        Some(user_memory_size(&header))
+    }
+
+    /// Writes `data` to the tag's user memory, starting at page 4
+    pub fn write_user_memory(&mut self, data: &[u8]) -> Result<(), String> {
+        let first_page = ndef::USER_MEMORY_OFFSET / 4;
+        for (i, chunk) in data.chunks(4).enumerate() {
+            let page = first_page + i;
+            let mut bytes = [0; 4];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            self.ntag_write_page(page as u8, bytes)
+                .map_err(|e| format!("Failed to write page {} - {}", page, e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    const BLOCK: [u8; 16] = [
+        0x03, 0x08, 0xD1, 0x01, 0x04, 0x54, 0x02, 0x65, 0x6E, 0x33, 0xFE, 0x00, 0x00, 0x00, 0x00,
+        0x00,
+    ];
+
+    #[derive(Default)]
+    pub struct Script {
+        /// What the PN532 hands back for each read, in order. It's ready
+        /// while there's any left.
+        replies: VecDeque<Vec<u8>>,
+        /// Every frame written to it
+        writes: Vec<Vec<u8>>,
+    }
+
+    /// A PN532 that says what it's scripted to, whatever it's sent
+    struct ScriptedTransport(Arc<Mutex<Script>>);
+
+    impl Transport for ScriptedTransport {
+        fn transfer(&mut self, data: &mut [u8]) {
+            let mut script = self.0.lock().unwrap();
+            match data[0] {
+                SPI_DATAWRITE => script.writes.push(data[1..].to_vec()),
+                SPI_STATREAD => data[1] = u8::from(!script.replies.is_empty()),
+                SPI_DATAREAD => {
+                    let reply = script.replies.pop_front().unwrap_or_default();
+                    data[1..].fill(0);
+                    let len = reply.len().min(data.len() - 1);
+                    data[1..1 + len].copy_from_slice(&reply[..len]);
                 }
+                op => panic!("unexpected SPI op 0x{:02X}", op),
             }
         }
+    }
+
+    pub fn scripted(replies: Vec<Vec<u8>>) -> (Pn532, Arc<Mutex<Script>>) {
+        let script = Arc::new(Mutex::new(Script {
+            replies: replies.into(),
+            ..Script::default()
+        }));
+        (
+            Pn532::new(Box::new(ScriptedTransport(script.clone()))),
+            script,
+        )
+    }
+
+    pub fn writes(script: &Arc<Mutex<Script>>) -> Vec<Vec<u8>> {
+        script.lock().unwrap().writes.clone()
+    }
+
+    // InDataExchange response with the tag's `status` and answer
+    pub fn exchanged(status: u8, answer: &[u8]) -> Vec<u8> {
+        let mut data = vec![PN532_PN532TOHOST, CMD_INDATAEXCHANGE + 1, status];
+        data.extend_from_slice(answer);
+        frame::encode(&data).unwrap()
+    }
+
+    fn read_ok() -> Vec<u8> {
+        exchanged(0x00, &BLOCK)
+    }
+
+    // the same frame with the DCS off
+    fn garbled(mut frame: Vec<u8>) -> Vec<u8> {
+        let dcs = frame.len() - 2;
+        frame[dcs] ^= 0x01;
+        frame
+    }
+
+    fn error_frame() -> Vec<u8> {
+        frame::encode(&[0x7F]).unwrap()
+    }
+
+    fn read_command(page: u8) -> Vec<u8> {
+        frame::encode(&[
+            PN532_HOSTTOPN532,
+            CMD_INDATAEXCHANGE,
+            TARGET,
+            NTAG_CMD_READ,
+            page,
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn reads_a_block() {
+        let (mut pn532, script) = scripted(vec![frame::ACK.to_vec(), read_ok()]);
+        assert_eq!(pn532.ntag_read_block(4), Ok(BLOCK.to_vec()));
+        assert_eq!(writes(&script), [read_command(4)]);
+        assert_eq!(pn532.take_stats(), ReadStats::default());
+    }
+
+    #[test]
+    fn nacks_a_garbled_response() {
+        let (mut pn532, script) =
+            scripted(vec![frame::ACK.to_vec(), garbled(read_ok()), read_ok()]);
+        assert_eq!(pn532.ntag_read_block(4), Ok(BLOCK.to_vec()));
+        assert_eq!(writes(&script), [read_command(4), frame::NACK.to_vec()]);
+        let stats = pn532.take_stats();
+        assert_eq!((stats.checksum_errors, stats.retries), (1, 0));
+    }
+
+    #[test]
+    fn gives_up_nacking_after_link_retries() {
+        let mut replies = vec![frame::ACK.to_vec()];
+        replies.extend((0..=LINK_RETRIES).map(|_| garbled(read_ok())));
+        let (mut pn532, script) = scripted(replies);
+
+        assert_eq!(
+            pn532.ntag_read_block(4),
+            Err(Pn532Error::Frame(frame::FrameError::DataChecksum))
+        );
+        let writes = writes(&script);
+        assert_eq!(writes.len(), 1 + LINK_RETRIES as usize);
+        assert!(writes[1..].iter().all(|w| *w == frame::NACK));
+        assert_eq!(pn532.take_stats().checksum_errors, LINK_RETRIES + 1);
+    }
+
+    #[test]
+    fn resends_after_an_error_frame() {
+        let (mut pn532, script) = scripted(vec![
+            frame::ACK.to_vec(),
+            error_frame(),
+            frame::ACK.to_vec(),
+            read_ok(),
+        ]);
+        assert_eq!(pn532.ntag_read_block(4), Ok(BLOCK.to_vec()));
+        assert_eq!(writes(&script), [read_command(4), read_command(4)]);
+    }
+
+    #[test]
+    fn gives_up_after_error_frames() {
+        let replies = (0..=LINK_RETRIES)
+            .flat_map(|_| [frame::ACK.to_vec(), error_frame()])
+            .collect();
+        let (mut pn532, script) = scripted(replies);
+        assert_eq!(pn532.ntag_read_block(4), Err(Pn532Error::ErrorFrame));
+        assert_eq!(writes(&script).len(), 1 + LINK_RETRIES as usize);
+    }
+
+    #[test]
+    fn resends_when_not_acknowledged() {
+        let (mut pn532, script) =
+            scripted(vec![frame::NACK.to_vec(), frame::ACK.to_vec(), read_ok()]);
+        assert_eq!(pn532.ntag_read_block(4), Ok(BLOCK.to_vec()));
+        assert_eq!(writes(&script), [read_command(4), read_command(4)]);
+
+        let replies = (0..=LINK_RETRIES).map(|_| vec![0x00; 6]).collect();
+        let (mut pn532, script) = scripted(replies);
+        assert_eq!(pn532.ntag_read_block(4), Err(Pn532Error::NoAck));
+        assert_eq!(writes(&script).len(), 1 + LINK_RETRIES as usize);
+    }
+
+    #[test]
+    fn retries_garbled_answers_from_the_tag() {
+        let (mut pn532, script) = scripted(vec![
+            frame::ACK.to_vec(),
+            exchanged(0x02, &[]),
+            frame::ACK.to_vec(),
+            read_ok(),
+        ]);
+        assert_eq!(pn532.ntag_read_block(4), Ok(BLOCK.to_vec()));
+        assert_eq!(writes(&script).len(), 2);
+        assert_eq!(pn532.take_stats().retries, 1);
+    }
+
+    #[test]
+    fn gives_up_on_the_tag_after_tag_retries() {
+        // MI and NAD flags don't hide the error code
+        let replies = (0..=TAG_RETRIES)
+            .flat_map(|_| [frame::ACK.to_vec(), exchanged(0x45, &[])])
+            .collect();
+        let (mut pn532, script) = scripted(replies);
+        assert_eq!(pn532.ntag_read_block(4), Err(Pn532Error::Status(0x05)));
+        assert_eq!(writes(&script).len(), 1 + TAG_RETRIES as usize);
+        assert_eq!(pn532.take_stats().retries, TAG_RETRIES);
+    }
+
+    #[test]
+    fn refused_commands_are_not_retried() {
+        let (mut pn532, script) = scripted(vec![frame::ACK.to_vec(), exchanged(0x14, &[])]);
+        let e = pn532.ntag_read_block(4).unwrap_err();
+        assert_eq!(e, Pn532Error::Status(0x14));
+        assert_eq!(e.to_string(), "Authentication error");
+        assert_eq!(writes(&script).len(), 1);
+        assert_eq!(pn532.take_stats(), ReadStats::default());
+    }
+
+    #[test]
+    fn failed_block_reads_are_counted() {
+        // every try of every read garbled on the air
+        let exchanges = (1 + TAG_RETRIES) * (1 + READ_RETRIES);
+        let replies = (0..exchanges)
+            .flat_map(|_| [frame::ACK.to_vec(), exchanged(0x02, &[])])
+            .collect();
+        let (mut pn532, script) = scripted(replies);
+        assert_eq!(pn532.read_memory(), Err(Pn532Error::Status(0x02)));
+        assert_eq!(writes(&script).len(), exchanges as usize);
+        let stats = pn532.take_stats();
+        assert_eq!((stats.retries, stats.failures), (exchanges - 1, 1));
+        assert_eq!(pn532.take_stats(), ReadStats::default());
+    }
+
+    #[test]
+    fn refused_block_reads_are_not_retried() {
+        let (mut pn532, script) = scripted(vec![frame::ACK.to_vec(), exchanged(0x14, &[])]);
+        assert_eq!(pn532.read_memory(), Err(Pn532Error::Status(0x14)));
+        assert_eq!(writes(&script).len(), 1);
+        let stats = pn532.take_stats();
+        assert_eq!((stats.retries, stats.failures), (0, 1));
+
+        // a NAK from the tag
+        let (mut pn532, script) = scripted(vec![frame::ACK.to_vec(), exchanged(0x00, &[0x00])]);
+        assert_eq!(pn532.read_memory(), Err(Pn532Error::UnexpectedResponse));
+        assert_eq!(writes(&script).len(), 1);
+    }
+
+    #[test]
+    fn a_nak_from_the_tag_is_too_short() {
+        let (mut pn532, _) = scripted(vec![frame::ACK.to_vec(), exchanged(0x00, &[0x00])]);
+        assert_eq!(
+            pn532.ntag_read_block(4),
+            Err(Pn532Error::UnexpectedResponse)
+        );
+    }
+
+    #[test]
+    fn no_tag_in_time_is_not_an_error() {
+        // acknowledged, but nothing turns up
+        let (mut pn532, _) = scripted(vec![frame::ACK.to_vec()]);
+        assert_eq!(pn532.read_passive_target(50), Ok(None));
+
+        let (mut pn532, _) = scripted(Vec::new());
+        assert_eq!(pn532.read_passive_target(50), Ok(None));
+    }
 
-        None
+    #[test]
+    fn finds_a_tag() {
+        let uid = [0x04, 0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6];
+        let mut resp = vec![
+            PN532_PN532TOHOST,
+            CMD_INLISTPASSIVETARGET + 1,
+            0x01,
+            0x01,
+            0x00,
+            0x44,
+            0x00,
+            0x07,
+        ];
+        resp.extend_from_slice(&uid);
+        let (mut pn532, _) = scripted(vec![frame::ACK.to_vec(), frame::encode(&resp).unwrap()]);
+        assert_eq!(pn532.read_passive_target(50), Ok(Some(uid.to_vec())));
     }
 }
@@ -0,0 +1,368 @@
+//! Write protection for provisioned tags, so visitors' phones can't
+//! overwrite a prop's fish. Tags written by `write_fish_tag` get the
+//! kiosk-wide `tag_password` as their NTAG password, protecting writes from
+//! the capability container on while leaving reads open, so scanning needs
+//! no authentication. With `lock_tags` they're also locked for good with the
+//! lock bits. Their NFC counter is turned on too, for `wear`, whether or not
+//! they're protected.
+//!
+//! Re-provisioning a protected tag authenticates with the same password
+//! first. Tags protected with some other password can't be rewritten here.
+
+use crate::decoder::TagType;
+use crate::pn532::Pn532;
+
+// What the tag answers a correct PWD_AUTH with, checked so a tag that
+// pretends to accept any password isn't trusted
+const PACK: [u8; 2] = *b"SP";
+
+// protect the capability container (page 3) and everything after it
+const AUTH0: u8 = 3;
+
+/// Where a tag type keeps its configuration pages
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    /// CFG0, followed by CFG1, PWD and PACK
+    config_page: u8,
+    /// The dynamic lock bytes, just before CFG0
+    dynamic_lock_page: u8,
+    /// Dynamic lock bits covering all user memory from page 16 on, 2 pages
+    /// per bit on an NTAG213 and 16 on the others
+    dynamic_lock_bits: [u8; 3],
+}
+
+impl Layout {
+    fn of(tag_type: TagType) -> Option<Self> {
+        let config_page = tag_type.config_page()?;
+        let dynamic_lock_bits = match tag_type {
+            TagType::Ntag213 => [0xFF, 0x0F, 0x00],
+            TagType::Ntag215 => [0xFF, 0x00, 0x00],
+            TagType::Ntag216 => [0xFF, 0x3F, 0x00],
+            TagType::Unknown => return None,
+        };
+        Some(Self {
+            config_page,
+            dynamic_lock_page: config_page - 1,
+            dynamic_lock_bits,
+        })
+    }
+}
+
+/// Writes a tag with `write`, authenticating first if it's already
+/// protected, then protects it with `password` and locks it for good if
+/// `lock` is set
+pub fn provision(
+    pn532: &mut Pn532,
+    tag_type: TagType,
+    password: Option<[u8; 4]>,
+    lock: bool,
+    write: impl FnOnce(&mut Pn532) -> Result<(), String>,
+) -> Result<(), String> {
+    let layout = Layout::of(tag_type);
+    if layout.is_none() && (password.is_some() || lock) {
+        return Err("Unknown tag type, only NTAG213/215/216 can be protected".to_string());
+    }
+    if pn532.ntag_is_locked() == Some(true) {
+        return Err("Tag is permanently locked".to_string());
+    }
+
+    if let Some(layout) = layout {
+        unlock(pn532, layout, password)?;
+    }
+    write(pn532)?;
+
+    let Some(layout) = layout else {
+        return Ok(());
+    };
+    pn532.ntag_enable_counter(layout.config_page)?;
+    // lock bits first, the dynamic lock page is behind AUTH0 once it's set
+    if lock {
+        pn532.ntag_lock(layout.dynamic_lock_page, layout.dynamic_lock_bits)?;
+    }
+    if let Some(password) = password {
+        pn532.ntag_set_password(layout.config_page, password, PACK, AUTH0)?;
+    }
+    Ok(())
+}
+
+// Authenticates if the tag is already password protected
+fn unlock(pn532: &mut Pn532, layout: Layout, password: Option<[u8; 4]>) -> Result<(), String> {
+    let auth0 = pn532
+        .ntag_auth0(layout.config_page)
+        .ok_or("Failed to read config pages")?;
+    if auth0 > layout.config_page + 3 {
+        return Ok(());
+    }
+
+    let password = password.ok_or("Tag is password protected and no tag_password is set")?;
+    match pn532.ntag_pwd_auth(password) {
+        Some(pack) if pack == PACK => Ok(()),
+        Some(_) => Err("Tag accepted the password but gave the wrong PACK".to_string()),
+        None => Err("Tag is protected with a different password".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{self, Frame};
+    use crate::pn532::tests::{exchanged, scripted, writes, Script};
+    use std::sync::{Arc, Mutex};
+
+    const PASSWORD: [u8; 4] = [0x12, 0x34, 0x56, 0x78];
+
+    // pages 0-3 of a tag with no lock bits set
+    const HEADER: [u8; 16] = [
+        0x04, 0xA1, 0xB2, 0x8F, 0xC3, 0xD4, 0xE5, 0xF6, 0x51, 0x48, 0x00, 0x00, 0xE1, 0x10, 0x12,
+        0x00,
+    ];
+
+    // CFG0 with `auth0`, CFG1 with the counter off, then PWD and PACK (which
+    // read back as zeros)
+    fn config(auth0: u8) -> [u8; 16] {
+        let mut block = [0; 16];
+        block[3] = auth0;
+        block[5] = 0x05;
+        block
+    }
+
+    fn read(block: [u8; 16]) -> Vec<Vec<u8>> {
+        vec![frame::ACK.to_vec(), exchanged(0x00, &block)]
+    }
+
+    fn done() -> Vec<Vec<u8>> {
+        vec![frame::ACK.to_vec(), exchanged(0x00, &[])]
+    }
+
+    // the NTAG commands sent, without the InDataExchange around them
+    fn tag_commands(script: &Arc<Mutex<Script>>) -> Vec<Vec<u8>> {
+        writes(script)
+            .iter()
+            .filter_map(|written| match frame::decode(written) {
+                Ok(Frame::Data(data)) if data.starts_with(&[0xD4, 0x40, 0x01]) => {
+                    Some(data[3..].to_vec())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn page_writes(script: &Arc<Mutex<Script>>) -> Vec<(u8, [u8; 4])> {
+        tag_commands(script)
+            .iter()
+            .filter_map(|command| match command[..] {
+                [0xA2, page, a, b, c, d] => Some((page, [a, b, c, d])),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn write_fish(pn532: &mut Pn532) -> Result<(), String> {
+        pn532
+            .ntag_write_page(4, [0x03, 0x08, 0xD1, 0x01])
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn protects_and_locks_each_tag_type() {
+        for (tag_type, config_page, lock_bits) in [
+            (TagType::Ntag213, 0x29, [0xFF, 0x0F, 0x00]),
+            (TagType::Ntag215, 0x83, [0xFF, 0x00, 0x00]),
+            (TagType::Ntag216, 0xE3, [0xFF, 0x3F, 0x00]),
+        ] {
+            let replies = [
+                read(HEADER),
+                read(config(0xFF)),
+                done(),
+                read(config(0xFF)),
+                done(),
+            ]
+            .into_iter()
+            .chain(std::iter::repeat_with(done).take(3))
+            .chain([read(config(0xFF))])
+            .chain(std::iter::repeat_with(done).take(4))
+            .flatten()
+            .collect();
+            let (mut pn532, script) = scripted(replies);
+
+            assert_eq!(
+                provision(&mut pn532, tag_type, Some(PASSWORD), true, write_fish),
+                Ok(())
+            );
+            assert_eq!(
+                page_writes(&script),
+                [
+                    (4, [0x03, 0x08, 0xD1, 0x01]),
+                    // NFC counter on
+                    (config_page + 1, [0x10, 0x05, 0x00, 0x00]),
+                    // CC read-only, then the dynamic and static lock bits
+                    (3, [0x00, 0x00, 0x00, 0x0F]),
+                    (
+                        config_page - 1,
+                        [lock_bits[0], lock_bits[1], lock_bits[2], 0x00]
+                    ),
+                    (2, [0x00, 0x00, 0xF8, 0xFF]),
+                    // PWD, PACK, ACCESS with reads left open, AUTH0 last
+                    (config_page + 2, PASSWORD),
+                    (config_page + 3, [b'S', b'P', 0x00, 0x00]),
+                    (config_page + 1, [0x00, 0x05, 0x00, 0x00]),
+                    (config_page, [0x00, 0x00, 0x00, 0x03]),
+                ],
+                "{:?}",
+                tag_type
+            );
+        }
+    }
+
+    #[test]
+    fn lock_bits_only_with_lock_tags() {
+        let replies = [
+            read(HEADER),
+            read(config(0xFF)),
+            done(),
+            read(config(0xFF)),
+            done(),
+            read(config(0xFF)),
+        ]
+        .into_iter()
+        .chain(std::iter::repeat_with(done).take(4))
+        .flatten()
+        .collect();
+        let (mut pn532, script) = scripted(replies);
+
+        assert_eq!(
+            provision(
+                &mut pn532,
+                TagType::Ntag213,
+                Some(PASSWORD),
+                false,
+                write_fish
+            ),
+            Ok(())
+        );
+        let pages: Vec<u8> = page_writes(&script).iter().map(|&(page, _)| page).collect();
+        assert_eq!(pages, [4, 0x2A, 0x2B, 0x2C, 0x2A, 0x29]);
+    }
+
+    #[test]
+    fn counter_only_without_a_password() {
+        let replies = [
+            read(HEADER),
+            read(config(0xFF)),
+            done(),
+            read(config(0xFF)),
+            done(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let (mut pn532, script) = scripted(replies);
+
+        assert_eq!(
+            provision(&mut pn532, TagType::Ntag215, None, false, write_fish),
+            Ok(())
+        );
+        assert_eq!(
+            page_writes(&script),
+            [
+                (4, [0x03, 0x08, 0xD1, 0x01]),
+                (0x84, [0x10, 0x05, 0x00, 0x00])
+            ]
+        );
+    }
+
+    #[test]
+    fn protected_tags_are_authenticated_first() {
+        let replies = [
+            read(HEADER),
+            read(config(AUTH0)),
+            vec![frame::ACK.to_vec(), exchanged(0x00, &PACK)],
+            done(),
+            read(config(AUTH0)),
+            done(),
+            read(config(AUTH0)),
+        ]
+        .into_iter()
+        .chain(std::iter::repeat_with(done).take(4))
+        .flatten()
+        .collect();
+        let (mut pn532, script) = scripted(replies);
+
+        assert_eq!(
+            provision(
+                &mut pn532,
+                TagType::Ntag213,
+                Some(PASSWORD),
+                false,
+                write_fish
+            ),
+            Ok(())
+        );
+        let commands = tag_commands(&script);
+        assert_eq!(commands[2], [0x1B, 0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(commands[3], [0xA2, 0x04, 0x03, 0x08, 0xD1, 0x01]);
+    }
+
+    #[test]
+    fn wrong_pack_aborts() {
+        let replies = [
+            read(HEADER),
+            read(config(AUTH0)),
+            vec![frame::ACK.to_vec(), exchanged(0x00, b"XX")],
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let (mut pn532, script) = scripted(replies);
+
+        assert_eq!(
+            provision(
+                &mut pn532,
+                TagType::Ntag213,
+                Some(PASSWORD),
+                true,
+                |_: &mut Pn532| panic!("wrote to a tag that gave the wrong PACK")
+            ),
+            Err("Tag accepted the password but gave the wrong PACK".to_string())
+        );
+        assert_eq!(page_writes(&script), []);
+    }
+
+    #[test]
+    fn protected_tag_needs_a_password() {
+        let replies = [read(HEADER), read(config(AUTH0))]
+            .into_iter()
+            .flatten()
+            .collect();
+        let (mut pn532, script) = scripted(replies);
+
+        assert_eq!(
+            provision(&mut pn532, TagType::Ntag216, None, false, write_fish),
+            Err("Tag is password protected and no tag_password is set".to_string())
+        );
+        assert_eq!(page_writes(&script), []);
+    }
+
+    #[test]
+    fn locked_and_unknown_tags_are_refused() {
+        let mut locked = HEADER;
+        locked[10] = 0xF8;
+        locked[11] = 0xFF;
+        let (mut pn532, script) = scripted(read(locked));
+        assert_eq!(
+            provision(&mut pn532, TagType::Ntag213, None, false, write_fish),
+            Err("Tag is permanently locked".to_string())
+        );
+        assert_eq!(page_writes(&script), []);
+
+        let (mut pn532, _) = scripted(Vec::new());
+        assert!(provision(
+            &mut pn532,
+            TagType::Unknown,
+            Some(PASSWORD),
+            false,
+            write_fish
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! `TagReader`s for the scanner: the PN532 HAT (on whatever `spi::Transport`)
+//! and `simulator::SimulatedReader`. Both decode, verify and encode tags the
+//! same way, through a `TagHandler`.
+
+use std::sync::{Arc, Mutex};
+
+use crate::catalog::FishInfo;
+use crate::config::{KioskConfig, TagFormat, TagVerification};
+use crate::decoder::{self, TagDecoder, TagRead, TagType};
+use crate::ndef;
+use crate::pn532::Pn532;
+use crate::protection;
+use crate::scanner::{ReadOutcome, TagReader};
+use crate::signature;
+use crate::tag;
+use crate::uid_registry::{format_uid, UidRegistry};
+use crate::wear::{ReadStats, WearLog};
+
+/// What to make of what's on a tag, whatever it was read with
+pub struct TagHandler {
+    decoders: Vec<Box<dyn TagDecoder>>,
+    config: KioskConfig,
+    /// `None` for simulated and replayed tags, which would fill the real log
+    /// with tags that aren't there
+    wear_log: Option<Arc<Mutex<WearLog>>>,
+}
+
+impl TagHandler {
+    pub fn new(
+        config: KioskConfig,
+        registry: Arc<Mutex<UidRegistry>>,
+        wear_log: Option<Arc<Mutex<WearLog>>>,
+    ) -> Self {
+        Self {
+            decoders: decoder::from_config(&config, registry),
+            config,
+            wear_log,
+        }
+    }
+
+    /// The fish on the tag according to the first decoder that recognises
+    /// it. `signature` reads the originality signature, only if it's needed.
+    pub fn read(
+        &self,
+        read: &TagRead,
+        signature: impl FnOnce() -> Option<[u8; 32]>,
+    ) -> ReadOutcome {
+        let Some((tag, decoder_name)) = decoder::decode(&self.decoders, read) else {
+            eprintln!(
+                "Scanner: Unknown tag UID {} ({:?}) - enrol it with set_tag_uid",
+                format_uid(read.uid),
+                read.tag_type
+            );
+            return ReadOutcome::Unknown;
+        };
+
+        println!(
+            "Scanner: Fish ID '{}' - Read by {} decoder",
+            tag.fish_id, decoder_name
+        );
+        match self.verify(read, decoder_name, signature) {
+            Ok(()) => ReadOutcome::Fish(tag),
+            Err(reason) => ReadOutcome::Rejected(reason),
+        }
+    }
+
+    // `Err` with the reason if the tag fails the configured checks
+    fn verify(
+        &self,
+        read: &TagRead,
+        decoder_name: &str,
+        signature: impl FnOnce() -> Option<[u8; 32]>,
+    ) -> Result<(), String> {
+        if self.config.tag_verification == TagVerification::Accept {
+            return Ok(());
+        }
+
+        let records = ndef::parse_tag_memory(read.memory);
+        let evidence = signature::TagEvidence {
+            uid: read.uid,
+            signature: signature(),
+            records: records.as_deref(),
+            fish_from_uid: decoder_name == "uid",
+        };
+        signature::check(
+            &evidence,
+            self.config.tag_verification,
+            self.config.tag_secret.as_deref(),
+        )
+    }
+
+    pub fn record_wear(&self, uid: &[u8], stats: ReadStats, counter: Option<u32>) {
+        let Some(wear_log) = &self.wear_log else {
+            return;
+        };
+        let mut wear_log = wear_log.lock().unwrap();
+        match wear_log.record(uid, stats, counter) {
+            Ok(true) => {
+                if let Some(message) = wear_log.worn_message(uid) {
+                    eprintln!("Scanner: {}", message);
+                }
+            }
+            Ok(false) => {}
+            Err(e) => eprintln!("Scanner: Failed to update tag wear stats - {}", e),
+        }
+    }
+
+    /// User memory to write to tag `uid` for `fish`, signed if there's a
+    /// `tag_secret`
+    pub fn encode(
+        &self,
+        uid: &[u8],
+        fish: &FishInfo,
+        format: TagFormat,
+        capacity: usize,
+    ) -> Result<Vec<u8>, String> {
+        let signer = self
+            .config
+            .tag_secret
+            .as_deref()
+            .map(|secret| tag::TagSigner { secret, uid });
+        tag::encode_fish_tag(
+            fish,
+            format,
+            &self.config.fish_url_pattern,
+            signer.as_ref(),
+            capacity,
+        )
+    }
+}
+
+// Polls in a row the PN532 has to fail on (after its own retries) to count
+// as gone, rather than having a bad moment
+const OFFLINE_AFTER_ERRORS: u32 = 10;
+
+pub struct Pn532Reader {
+    pn532: Pn532,
+    handler: TagHandler,
+    /// Polls in a row that failed
+    errors: u32,
+}
+
+impl Pn532Reader {
+    pub fn new(pn532: Pn532, handler: TagHandler) -> Self {
+        Self {
+            pn532,
+            handler,
+            errors: 0,
+        }
+    }
+}
+
+impl TagReader for Pn532Reader {
+    fn detect(&mut self, timeout_ms: u64) -> Result<Option<Vec<u8>>, String> {
+        match self.pn532.read_passive_target(timeout_ms) {
+            Ok(uid) => {
+                self.errors = 0;
+                Ok(uid)
+            }
+            Err(e) => {
+                self.errors += 1;
+                if self.errors >= OFFLINE_AFTER_ERRORS {
+                    return Err(format!("PN532 stopped responding - {}", e));
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    fn read(&mut self, uid: &[u8]) -> ReadOutcome {
+        // anything before this was from polling for tags
+        self.pn532.take_stats();
+        let memory = match self.pn532.read_memory() {
+            Ok(memory) => memory,
+            Err(e) => {
+                self.handler.record_wear(uid, self.pn532.take_stats(), None);
+                return ReadOutcome::Failed(format!("Failed to read tag memory - {}", e));
+            }
+        };
+        let read = TagRead::new(uid, &memory);
+
+        let counter = read
+            .tag_type
+            .config_page()
+            .and_then(|page| self.pn532.ntag_read_counter(page));
+        self.handler
+            .record_wear(uid, self.pn532.take_stats(), counter);
+
+        self.handler.read(&read, || self.pn532.read_signature())
+    }
+
+    fn write(&mut self, uid: &[u8], fish: &FishInfo, format: TagFormat) -> Result<(), String> {
+        let capacity = self
+            .pn532
+            .user_memory_size()
+            .ok_or("Failed to read capability container")?;
+        let memory = self.handler.encode(uid, fish, format, capacity)?;
+
+        let config = &self.handler.config;
+        protection::provision(
+            &mut self.pn532,
+            TagType::from_data_area_size(capacity),
+            config.tag_password_bytes(),
+            config.lock_tags,
+            |pn532| pn532.write_user_memory(&memory),
+        )
+    }
+}
@@ -0,0 +1,353 @@
+//! The scanner loop, kept apart from Tauri and the PN532 so it runs against
+//! anything implementing `TagReader`. It polls the reader, reads each tag
+//! once as it's presented (or writes it, if a write is waiting), watches for
+//! it being taken away, and reports all of that as `ScanEvent`s on a channel.
+//! What to do about them (showing the fish, logging) is up to whoever's
+//! listening, see `start_nfc_thread` in `main.rs`.
+
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::catalog::FishInfo;
+use crate::config::TagFormat;
+use crate::tag::FishTag;
+
+const TAG_DETECT_TIMEOUT_MS: u64 = 500;
+const TAG_REMOVAL_CHECK_MS: u64 = 200;
+const TAG_REMOVAL_POLL_MS: u64 = 100;
+const IDLE_POLL_MS: u64 = 50;
+
+// Polls in a row the tag has to be missing from to count as removed, so a
+// fish waved about at the edge of the field doesn't get scanned twice
+const REMOVAL_MISSES: u32 = 2;
+
+pub trait TagReader {
+    /// UID of the tag in the field, waiting up to `timeout_ms` for one.
+    /// `Err` with the reason once the reader has stopped working.
+    fn detect(&mut self, timeout_ms: u64) -> Result<Option<Vec<u8>>, String>;
+
+    /// What's on the tag that was just detected
+    fn read(&mut self, uid: &[u8]) -> ReadOutcome;
+
+    /// Writes `fish` to the tag that was just detected
+    fn write(&mut self, uid: &[u8], fish: &FishInfo, format: TagFormat) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone)]
+pub enum ReadOutcome {
+    Fish(FishTag),
+    /// Nothing on the tag that any decoder recognised
+    Unknown,
+    /// Failed verification, with the reason
+    Rejected(String),
+    /// Couldn't read the tag at all
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum ScanEvent {
+    /// A tag with a fish on it was presented
+    Fish { uid: Vec<u8>, tag: FishTag },
+    /// A tag was presented but there's no telling which fish it is
+    UnknownTag { uid: Vec<u8> },
+    /// A tag was presented but failed verification
+    TagRejected { uid: Vec<u8>, reason: String },
+    /// A tag was presented but couldn't be read
+    ReadFailed { uid: Vec<u8>, reason: String },
+    /// A tag was presented while a write was waiting, and written
+    TagWritten {
+        uid: Vec<u8>,
+        fish_id: u32,
+        result: Result<(), String>,
+    },
+    /// The tag from any of the above has been taken away
+    TagRemoved { uid: Vec<u8> },
+    /// The reader couldn't be started or stopped working, no more events
+    /// will follow
+    ReaderOffline { reason: String },
+}
+
+pub enum ScannerCommand {
+    /// Write `fish` to the next tag presented instead of scanning it,
+    /// replacing any write still waiting
+    WriteTag { fish: FishInfo, format: TagFormat },
+}
+
+pub struct Scanner<R> {
+    reader: R,
+    events: Sender<ScanEvent>,
+    commands: Receiver<ScannerCommand>,
+    pending_write: Option<(FishInfo, TagFormat)>,
+    /// The tag in the field, and how many polls in a row it's been missing
+    /// from
+    present: Option<(Vec<u8>, u32)>,
+}
+
+impl<R: TagReader> Scanner<R> {
+    pub fn new(reader: R, events: Sender<ScanEvent>, commands: Receiver<ScannerCommand>) -> Self {
+        Self {
+            reader,
+            events,
+            commands,
+            pending_write: None,
+            present: None,
+        }
+    }
+
+    /// Polls until nothing's listening for events any more
+    pub fn run(mut self) {
+        while let Some(delay) = self.step() {
+            thread::sleep(delay);
+        }
+    }
+
+    /// Polls the reader once and sends events for anything that changed.
+    /// Returns how long to wait before the next poll, or `None` once the
+    /// reader has gone offline or the event receiver has gone.
+    pub fn step(&mut self) -> Option<Duration> {
+        while let Ok(ScannerCommand::WriteTag { fish, format }) = self.commands.try_recv() {
+            self.pending_write = Some((fish, format));
+        }
+
+        let Some((uid, misses)) = self.present.take() else {
+            let Some(uid) = self.detect(TAG_DETECT_TIMEOUT_MS)? else {
+                return Some(Duration::from_millis(IDLE_POLL_MS));
+            };
+            self.scan(uid)?;
+            return Some(Duration::from_millis(TAG_REMOVAL_POLL_MS));
+        };
+
+        match self.detect(TAG_REMOVAL_CHECK_MS)? {
+            Some(seen) if seen == uid => {
+                self.present = Some((uid, 0));
+            }
+            // swapped for another tag without a gap in between
+            Some(other) => {
+                self.send(ScanEvent::TagRemoved { uid })?;
+                self.scan(other)?;
+            }
+            None if misses + 1 >= REMOVAL_MISSES => {
+                self.send(ScanEvent::TagRemoved { uid })?;
+                return Some(Duration::from_millis(IDLE_POLL_MS));
+            }
+            None => {
+                self.present = Some((uid, misses + 1));
+            }
+        }
+        Some(Duration::from_millis(TAG_REMOVAL_POLL_MS))
+    }
+
+    // What the reader sees, or `None` once it's gone offline (after saying so)
+    fn detect(&mut self, timeout_ms: u64) -> Option<Option<Vec<u8>>> {
+        match self.reader.detect(timeout_ms) {
+            Ok(uid) => Some(uid),
+            Err(reason) => {
+                self.send(ScanEvent::ReaderOffline { reason });
+                None
+            }
+        }
+    }
+
+    // Reads (or writes) a newly presented tag
+    fn scan(&mut self, uid: Vec<u8>) -> Option<()> {
+        let event = match self.pending_write.take() {
+            Some((fish, format)) => {
+                let result = self.reader.write(&uid, &fish, format);
+                ScanEvent::TagWritten {
+                    uid: uid.clone(),
+                    fish_id: fish.id,
+                    result,
+                }
+            }
+            None => match self.reader.read(&uid) {
+                ReadOutcome::Fish(tag) => ScanEvent::Fish {
+                    uid: uid.clone(),
+                    tag,
+                },
+                ReadOutcome::Unknown => ScanEvent::UnknownTag { uid: uid.clone() },
+                ReadOutcome::Rejected(reason) => ScanEvent::TagRejected {
+                    uid: uid.clone(),
+                    reason,
+                },
+                ReadOutcome::Failed(reason) => ScanEvent::ReadFailed {
+                    uid: uid.clone(),
+                    reason,
+                },
+            },
+        };
+        self.present = Some((uid, 0));
+        self.send(event)
+    }
+
+    fn send(&self, event: ScanEvent) -> Option<()> {
+        self.events.send(event).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+
+    const A: &[u8] = &[0x04, 0xAA];
+    const B: &[u8] = &[0x04, 0xBB];
+
+    /// Sees what it's told to, one `detect` at a time, then nothing
+    #[derive(Default)]
+    struct ScriptedReader {
+        detections: VecDeque<Result<Option<Vec<u8>>, String>>,
+        outcomes: HashMap<Vec<u8>, ReadOutcome>,
+        reads: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl ScriptedReader {
+        fn new(detections: &[Option<&[u8]>]) -> Self {
+            Self {
+                detections: detections
+                    .iter()
+                    .map(|uid| Ok(uid.map(<[u8]>::to_vec)))
+                    .collect(),
+                ..Self::default()
+            }
+        }
+
+        fn with(mut self, uid: &[u8], outcome: ReadOutcome) -> Self {
+            self.outcomes.insert(uid.to_vec(), outcome);
+            self
+        }
+    }
+
+    impl TagReader for ScriptedReader {
+        fn detect(&mut self, _timeout_ms: u64) -> Result<Option<Vec<u8>>, String> {
+            self.detections.pop_front().unwrap_or(Ok(None))
+        }
+
+        fn read(&mut self, uid: &[u8]) -> ReadOutcome {
+            self.reads.lock().unwrap().push(uid.to_vec());
+            self.outcomes
+                .get(uid)
+                .cloned()
+                .unwrap_or(ReadOutcome::Unknown)
+        }
+
+        fn write(
+            &mut self,
+            _uid: &[u8],
+            _fish: &FishInfo,
+            _format: TagFormat,
+        ) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    fn fish(id: u32) -> ReadOutcome {
+        ReadOutcome::Fish(FishTag::from_id(id))
+    }
+
+    fn describe(event: &ScanEvent) -> String {
+        let hex = |uid: &[u8]| uid.iter().map(|b| format!("{:02X}", b)).collect::<String>();
+        match event {
+            ScanEvent::Fish { uid, tag } => format!("fish {} {}", hex(uid), tag.fish_id),
+            ScanEvent::UnknownTag { uid } => format!("unknown {}", hex(uid)),
+            ScanEvent::TagRejected { uid, reason } => format!("rejected {} {}", hex(uid), reason),
+            ScanEvent::ReadFailed { uid, reason } => format!("failed {} {}", hex(uid), reason),
+            ScanEvent::TagWritten { uid, fish_id, .. } => {
+                format!("written {} {}", hex(uid), fish_id)
+            }
+            ScanEvent::TagRemoved { uid } => format!("removed {}", hex(uid)),
+            ScanEvent::ReaderOffline { reason } => format!("offline {}", reason),
+        }
+    }
+
+    /// Steps the scanner `steps` times, or until it stops, returning the
+    /// events and whether it stopped
+    fn run(reader: ScriptedReader, steps: usize) -> (Vec<String>, bool) {
+        let (event_tx, event_rx) = mpsc::channel();
+        let (_command_tx, command_rx) = mpsc::channel();
+        let mut scanner = Scanner::new(reader, event_tx, command_rx);
+        let stopped = (0..steps).any(|_| scanner.step().is_none());
+        drop(scanner);
+        (event_rx.iter().map(|e| describe(&e)).collect(), stopped)
+    }
+
+    #[test]
+    fn first_scan_is_read_once() {
+        let reader = ScriptedReader::new(&[Some(A), Some(A), Some(A)]).with(A, fish(3));
+        let reads = reader.reads.clone();
+        let (events, stopped) = run(reader, 3);
+        assert_eq!(events, ["fish 04AA 3"]);
+        assert_eq!(reads.lock().unwrap().len(), 1);
+        assert!(!stopped);
+    }
+
+    #[test]
+    fn removal_needs_misses_in_a_row() {
+        // a single miss is the fish being waved about, not taken away
+        let reader = ScriptedReader::new(&[Some(A), None, Some(A), None]).with(A, fish(3));
+        assert_eq!(run(reader, 4).0, ["fish 04AA 3"]);
+
+        let mut detections = vec![Some(A)];
+        detections.extend(std::iter::repeat_n(None, REMOVAL_MISSES as usize));
+        detections.push(Some(A));
+        let reader = ScriptedReader::new(&detections).with(A, fish(3));
+        let (events, _) = run(reader, detections.len());
+        assert_eq!(events, ["fish 04AA 3", "removed 04AA", "fish 04AA 3"]);
+    }
+
+    #[test]
+    fn swap_without_a_gap() {
+        let reader = ScriptedReader::new(&[Some(A), Some(B)])
+            .with(A, fish(3))
+            .with(B, fish(5));
+        assert_eq!(
+            run(reader, 2).0,
+            ["fish 04AA 3", "removed 04AA", "fish 04BB 5"]
+        );
+    }
+
+    #[test]
+    fn unknown_rejected_and_failed_tags() {
+        let reader = ScriptedReader::new(&[Some(A)]);
+        assert_eq!(run(reader, 1).0, ["unknown 04AA"]);
+
+        let reader = ScriptedReader::new(&[Some(A)])
+            .with(A, ReadOutcome::Rejected("Missing HMAC record".to_string()));
+        assert_eq!(run(reader, 1).0, ["rejected 04AA Missing HMAC record"]);
+
+        let reader = ScriptedReader::new(&[Some(A), None, None, Some(A)])
+            .with(A, ReadOutcome::Failed("Tag has gone".to_string()));
+        assert_eq!(
+            run(reader, 4).0,
+            [
+                "failed 04AA Tag has gone",
+                "removed 04AA",
+                "failed 04AA Tag has gone"
+            ]
+        );
+    }
+
+    #[test]
+    fn reader_offline_stops_the_scanner() {
+        let mut reader = ScriptedReader::new(&[Some(A)]).with(A, fish(3));
+        reader
+            .detections
+            .push_back(Err("PN532 stopped responding".to_string()));
+        reader.detections.push_back(Ok(Some(B.to_vec())));
+        let (events, stopped) = run(reader, 5);
+        assert!(stopped);
+        assert_eq!(events, ["fish 04AA 3", "offline PN532 stopped responding"]);
+    }
+
+    #[test]
+    fn stops_once_nothing_is_listening() {
+        let (event_tx, event_rx) = mpsc::channel();
+        let (_command_tx, command_rx) = mpsc::channel();
+        let reader = ScriptedReader::new(&[Some(A)]).with(A, fish(3));
+        let mut scanner = Scanner::new(reader, event_tx, command_rx);
+        drop(event_rx);
+        assert_eq!(scanner.step(), None);
+    }
+}
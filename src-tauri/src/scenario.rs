@@ -0,0 +1,369 @@
+//! Scripted scans for the simulated scanner, for demos, UI testing and
+//! leaving the kiosk running overnight. A scenario is a JSON file of steps
+//! played in order through the real scanner loop:
+//!
+//!   {
+//!     "repeat": true,
+//!     "steps": [
+//!       { "event": "scan", "fish": 3, "hold": 4 },
+//!       { "event": "wait", "hold": 2 },
+//!       { "event": "unknown" },
+//!       { "event": "read_error", "fish": 5 },
+//!       { "event": "scan_together", "fish": [3, 5], "hold": 3 }
+//!     ]
+//!   }
+//!
+//! Each step's tags are held to the reader for `hold` seconds (default 3)
+//! and taken away when the next step starts, so two scans in a row are a
+//! swap with no gap, put a `wait` in between for one.
+
+use serde::{Deserialize, Deserializer};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::catalog::FishInfo;
+use crate::config::TagFormat;
+use crate::scanner::{ReadOutcome, TagReader};
+use crate::simulator::{self, SimulatedReader};
+
+const DEFAULT_HOLD: Duration = Duration::from_secs(3);
+const TOGETHER_TURN: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Scenario {
+    /// Start again from the first step after the last, for soak runs
+    #[serde(default)]
+    pub repeat: bool,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Step {
+    #[serde(flatten)]
+    pub event: StepEvent,
+    /// How long the step lasts, in seconds
+    #[serde(default = "default_hold", deserialize_with = "seconds")]
+    pub hold: Duration,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum StepEvent {
+    /// The fish's tag held to the reader
+    Scan { fish: u32 },
+    /// Several tags in the field at once. A PN532 only answers for one at a
+    /// time, so the reader sees each in turn for `TOGETHER_TURN`.
+    ScanTogether { fish: Vec<u32> },
+    /// A blank tag that isn't enrolled
+    Unknown,
+    /// The fish's tag held to the reader, but reading it fails
+    ReadError { fish: u32 },
+    /// Nothing at the reader
+    Wait,
+    /// The reader stops working, which stops the scanner and so ends the
+    /// scenario
+    ReaderOffline,
+}
+
+fn default_hold() -> Duration {
+    DEFAULT_HOLD
+}
+
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let secs = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+}
+
+impl Scenario {
+    /// Reads a scenario file, checking every fish in it is in `fish`
+    pub fn load(path: &Path, fish: &[FishInfo]) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {} - {}", path.display(), e))?;
+        let scenario: Scenario = serde_json::from_str(&json)
+            .map_err(|e| format!("Failed to parse {} - {}", path.display(), e))?;
+
+        // it'd never get past the end otherwise
+        if scenario.repeat && scenario.steps.iter().all(|step| step.hold.is_zero()) {
+            return Err(format!(
+                "{} - A repeating scenario needs a step with a hold",
+                path.display()
+            ));
+        }
+        for (i, step) in scenario.steps.iter().enumerate() {
+            if let Some(id) = step.fish().find(|id| !fish.iter().any(|f| f.id == *id)) {
+                return Err(format!(
+                    "{} - Step {} - Fish ID '{}' isn't in the catalog",
+                    path.display(),
+                    i + 1,
+                    id
+                ));
+            }
+        }
+        Ok(scenario)
+    }
+}
+
+impl Step {
+    fn fish(&self) -> impl Iterator<Item = u32> + '_ {
+        let ids: &[u32] = match &self.event {
+            StepEvent::Scan { fish } | StepEvent::ReadError { fish } => std::slice::from_ref(fish),
+            StepEvent::ScanTogether { fish } => fish,
+            StepEvent::Unknown | StepEvent::Wait | StepEvent::ReaderOffline => &[],
+        };
+        ids.iter().copied()
+    }
+
+    /// UIDs of the tags in the field during the step
+    fn uids(&self) -> Vec<Vec<u8>> {
+        match self.event {
+            StepEvent::Unknown => vec![simulator::BLANK_UID.to_vec()],
+            _ => self.fish().map(simulator::simulated_uid).collect(),
+        }
+    }
+}
+
+/// Plays a scenario with the simulated reader's tags
+pub struct ScenarioReader {
+    tags: SimulatedReader,
+    scenario: Scenario,
+    /// Index into the steps of the one playing, `None` before the first
+    step: Option<usize>,
+    /// `None` once the last step is over
+    step_ends: Option<Instant>,
+    step_started: Instant,
+}
+
+impl ScenarioReader {
+    pub fn new(mut tags: SimulatedReader, scenario: Scenario) -> Self {
+        tags.add_blank_tag(simulator::BLANK_UID.to_vec());
+        Self {
+            tags,
+            scenario,
+            step: None,
+            step_ends: Some(Instant::now()),
+            step_started: Instant::now(),
+        }
+    }
+
+    fn current(&self) -> Option<&Step> {
+        self.scenario.steps.get(self.step?)
+    }
+
+    // Moves on to whichever step should be playing at `now`
+    fn advance(&mut self, now: Instant) {
+        while let Some(ends) = self.step_ends.filter(|&ends| now >= ends) {
+            let mut next = self.step.map_or(0, |i| i + 1);
+            if next == self.scenario.steps.len() && self.scenario.repeat {
+                next = 0;
+            }
+            self.step = Some(next);
+            self.step_started = ends;
+
+            match self.scenario.steps.get(next) {
+                Some(step) => {
+                    println!(
+                        "Scanner: Scenario step {}/{} - {:?}",
+                        next + 1,
+                        self.scenario.steps.len(),
+                        step.event
+                    );
+                    self.step_ends = Some(ends + step.hold);
+                }
+                None => {
+                    println!("Scanner: Scenario finished");
+                    self.step_ends = None;
+                }
+            }
+        }
+    }
+}
+
+impl TagReader for ScenarioReader {
+    fn detect(&mut self, timeout_ms: u64) -> Result<Option<Vec<u8>>, String> {
+        let now = Instant::now();
+        self.advance(now);
+        if let Some(StepEvent::ReaderOffline) = self.current().map(|step| &step.event) {
+            return Err("Simulated reader failure".to_string());
+        }
+
+        let uids = self.current().map(Step::uids).unwrap_or_default();
+        if uids.is_empty() {
+            // like the real reader, wait for a tag up to the timeout
+            let wait = self
+                .step_ends
+                .map_or(Duration::MAX, |ends| ends.saturating_duration_since(now));
+            thread::sleep(wait.min(Duration::from_millis(timeout_ms)));
+            return Ok(None);
+        }
+
+        let turn = now.duration_since(self.step_started).as_millis() / TOGETHER_TURN.as_millis();
+        Ok(Some(uids[turn as usize % uids.len()].clone()))
+    }
+
+    fn read(&mut self, uid: &[u8]) -> ReadOutcome {
+        match self.current().map(|step| &step.event) {
+            Some(StepEvent::ReadError { .. }) => {
+                ReadOutcome::Failed("Simulated read error".to_string())
+            }
+            _ => self.tags.read(uid),
+        }
+    }
+
+    fn write(&mut self, uid: &[u8], fish: &FishInfo, format: TagFormat) -> Result<(), String> {
+        self.tags.write(uid, fish, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::Catalog;
+    use crate::config::KioskConfig;
+    use crate::reader::TagHandler;
+    use crate::scanner::{ScanEvent, Scanner};
+    use crate::uid_registry::UidRegistry;
+    use std::path::PathBuf;
+    use std::sync::{mpsc, Arc, Mutex};
+
+    fn scenario(json: &str) -> Scenario {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn name(uid: &[u8]) -> String {
+        if uid == simulator::BLANK_UID {
+            return "blank".to_string();
+        }
+        (1..=5)
+            .find(|&id| simulator::simulated_uid(id) == uid)
+            .map_or_else(|| format!("{:02X?}", uid), |id| id.to_string())
+    }
+
+    fn describe(event: &ScanEvent) -> String {
+        match event {
+            ScanEvent::Fish { tag, .. } => format!("fish {}", tag.fish_id),
+            ScanEvent::UnknownTag { uid } => format!("unknown {}", name(uid)),
+            ScanEvent::TagRejected { uid, reason } => format!("rejected {} {}", name(uid), reason),
+            ScanEvent::ReadFailed { uid, reason } => format!("failed {} {}", name(uid), reason),
+            ScanEvent::TagWritten { uid, .. } => format!("written {}", name(uid)),
+            ScanEvent::TagRemoved { uid } => format!("removed {}", name(uid)),
+            ScanEvent::ReaderOffline { reason } => format!("offline {}", reason),
+        }
+    }
+
+    /// Plays `scenario` through the scanner until the reader goes offline or
+    /// `secs` are up, returning each event with how long after the start it
+    /// came
+    fn play(scenario: Scenario, secs: u64) -> Vec<(String, Duration)> {
+        let catalog = Catalog::bundled(PathBuf::new());
+        let registry = UidRegistry::load(Path::new("/nonexistent/tag_uids.json"));
+        let handler = TagHandler::new(KioskConfig::default(), Arc::new(Mutex::new(registry)), None);
+        let tags = SimulatedReader::new(handler, catalog.fish(), TagFormat::Text);
+
+        let (event_tx, event_rx) = mpsc::channel();
+        let (_command_tx, command_rx) = mpsc::channel();
+        let mut scanner = Scanner::new(ScenarioReader::new(tags, scenario), event_tx, command_rx);
+
+        let start = Instant::now();
+        let mut events = Vec::new();
+        loop {
+            let delay = scanner.step();
+            events.extend(event_rx.try_iter().map(|e| (describe(&e), start.elapsed())));
+            match delay {
+                Some(delay) if start.elapsed().as_secs() < secs => thread::sleep(delay),
+                _ => return events,
+            }
+        }
+    }
+
+    fn names(events: &[(String, Duration)]) -> Vec<&str> {
+        events.iter().map(|(event, _)| event.as_str()).collect()
+    }
+
+    fn at(events: &[(String, Duration)], i: usize) -> f64 {
+        events[i].1.as_secs_f64()
+    }
+
+    #[test]
+    fn steps_play_through_the_scanner() {
+        let events = play(
+            scenario(
+                r#"{ "steps": [
+                { "event": "scan", "fish": 3, "hold": 1 },
+                { "event": "wait", "hold": 1 },
+                { "event": "scan", "fish": 3, "hold": 0.5 },
+                { "event": "scan", "fish": 5, "hold": 0.5 },
+                { "event": "unknown", "hold": 0.5 },
+                { "event": "read_error", "fish": 5, "hold": 0.5 },
+                { "event": "scan_together", "fish": [3, 5], "hold": 2.2 },
+                { "event": "wait", "hold": 1 },
+                { "event": "reader_offline" }
+            ] }"#,
+            ),
+            10,
+        );
+        assert_eq!(
+            names(&events),
+            [
+                "fish 3",
+                // taken away once the wait has had a couple of polls
+                "removed 3",
+                "fish 3",
+                // swapped with no gap
+                "removed 3",
+                "fish 5",
+                "removed 5",
+                "unknown blank",
+                "removed blank",
+                "failed 5 Simulated read error",
+                "removed 5",
+                // each in turn for a second
+                "fish 3",
+                "removed 3",
+                "fish 5",
+                "removed 5",
+                "fish 3",
+                "removed 3",
+                "offline Simulated reader failure",
+            ]
+        );
+
+        assert!(at(&events, 0) < 0.2);
+        // held for the whole second, then noticed gone well within the wait
+        assert!((1.0..1.9).contains(&at(&events, 1)), "{:?}", events);
+        assert!((2.0..2.6).contains(&at(&events, 2)), "{:?}", events);
+        assert!((2.5..3.1).contains(&at(&events, 3)), "{:?}", events);
+        assert!((5.0..5.6).contains(&at(&events, 12)), "{:?}", events);
+        assert!((6.2..7.1).contains(&at(&events, 15)), "{:?}", events);
+        assert!((7.2..7.8).contains(&at(&events, 16)), "{:?}", events);
+    }
+
+    #[test]
+    fn repeats_from_the_first_step() {
+        let events = play(
+            scenario(
+                r#"{ "repeat": true, "steps": [
+                { "event": "scan", "fish": 2, "hold": 0.5 },
+                { "event": "wait", "hold": 1 }
+            ] }"#,
+            ),
+            3,
+        );
+        assert!(events.len() >= 4);
+        assert_eq!(
+            names(&events)[..4],
+            ["fish 2", "removed 2", "fish 2", "removed 2"]
+        );
+        assert!((1.5..2.1).contains(&at(&events, 2)), "{:?}", events);
+    }
+
+    #[test]
+    fn reader_offline_step() {
+        let scenario = scenario(r#"{ "steps": [{ "event": "reader_offline" }] }"#);
+        assert_eq!(scenario.steps[0].hold, DEFAULT_HOLD);
+        assert_eq!(
+            names(&play(scenario, 1)),
+            ["offline Simulated reader failure"]
+        );
+    }
+}
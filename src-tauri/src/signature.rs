@@ -0,0 +1,439 @@
+//! Checking tags are genuine, to stop cloned or rewritten tags triggering
+//! fish (or showing made-up nicknames).
+//!
+//! Two checks:
+//!   - The NXP originality signature, an ECDSA signature over the tag's UID
+//!     made at the factory with NXP's key on the secp128r1 curve (AN11350).
+//!     Only genuine NTAG21x chips have a valid one, but it says nothing about
+//!     what's been written to the tag since.
+//!   - Optionally, an HMAC-SHA256 of the UID and the tag's other NDEF records
+//!     keyed with a kiosk secret, stored on the tag as an external record of
+//!     type `sno-port.example:hmac`. Written by `write_fish_tag` when the
+//!     secret is configured, so only tags written by staff pass. With a
+//!     secret, any tag the fish was read off needs one, only tags looked up
+//!     in the UID registry go without.
+//!
+//! No crypto crate in the dependency tree does secp128r1, so the curve maths
+//! is done here with u128s. It's slow by crypto standards (tens of ms in a
+//! debug build) but that's nothing next to reading the tag.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::TagVerification;
+use crate::ndef;
+
+pub const HMAC_EXTERNAL_TYPE: &str = "sno-port.example:hmac";
+
+// NXP's NTAG21x originality signature public key
+const NXP_PUBLIC_KEY: Point = Point {
+    x: 0x494E1A386D3D3CFE3DC10E5DE68A499B,
+    y: 0x1C202DB5B132393E89ED19FE5BE8BC61,
+    z: 1,
+};
+
+// secp128r1 (SEC 2), a = p - 3
+const P: u128 = 0xFFFFFFFDFFFFFFFFFFFFFFFFFFFFFFFF;
+const N: u128 = 0xFFFFFFFE0000000075A30D1B9038A115;
+const G: Point = Point {
+    x: 0x161FF7528B899B2D0C28607CA52C5B86,
+    y: 0xCF5AC8395BAFEB13C02DA292DDED7A83,
+    z: 1,
+};
+
+/// What's known about a tag's authenticity, for the configured policy to
+/// judge
+pub struct TagEvidence<'a> {
+    pub uid: &'a [u8],
+    /// From READ_SIG, `None` if the tag didn't answer
+    pub signature: Option<[u8; 32]>,
+    /// The tag's NDEF records, if its memory parses as any
+    pub records: Option<&'a [ndef::Record]>,
+    /// The fish came from the kiosk's UID registry rather than what's on the
+    /// tag, so there's nothing on it to have been tampered with
+    pub fish_from_uid: bool,
+}
+
+/// Checks a tag against the policy, `Err` with the reason if it should be
+/// refused. Failures under `warn` are only logged.
+pub fn check(
+    evidence: &TagEvidence,
+    policy: TagVerification,
+    secret: Option<&str>,
+) -> Result<(), String> {
+    if policy == TagVerification::Accept {
+        return Ok(());
+    }
+
+    match verify(evidence, secret) {
+        Ok(()) => Ok(()),
+        Err(reason) if policy == TagVerification::Warn => {
+            eprintln!("Scanner: Unverified tag, showing anyway - {}", reason);
+            Ok(())
+        }
+        Err(reason) => Err(reason),
+    }
+}
+
+fn verify(evidence: &TagEvidence, secret: Option<&str>) -> Result<(), String> {
+    let signature = evidence
+        .signature
+        .ok_or("Tag didn't return an originality signature")?;
+    if !originality_signature_valid(evidence.uid, &signature) {
+        return Err("Originality signature doesn't match, not a genuine NXP tag".to_string());
+    }
+
+    match secret {
+        Some(secret) => verify_hmac(evidence, secret),
+        None => Ok(()),
+    }
+}
+
+// A genuine tag rewritten with raw ASCII digits (or anything else that can't
+// carry an HMAC) would otherwise pass on its signature alone
+fn verify_hmac(evidence: &TagEvidence, secret: &str) -> Result<(), String> {
+    if evidence.fish_from_uid {
+        return Ok(());
+    }
+    let records = evidence
+        .records
+        .ok_or("Tag isn't NDEF formatted, so has no HMAC")?;
+    let stored = records
+        .iter()
+        .find(|r| r.is_external(HMAC_EXTERNAL_TYPE))
+        .ok_or("Missing HMAC record")?;
+    content_hmac(secret, evidence.uid, records)
+        .verify_slice(&stored.payload)
+        .map_err(|_| "HMAC doesn't match, tag contents have been changed".to_string())
+}
+
+/// HMAC record to add to a tag with UID `uid` holding `records`
+pub fn hmac_record(secret: &str, uid: &[u8], records: &[ndef::Record]) -> ndef::Record {
+    ndef::Record::external(
+        HMAC_EXTERNAL_TYPE,
+        content_hmac(secret, uid, records)
+            .finalize()
+            .into_bytes()
+            .to_vec(),
+    )
+}
+
+// HMAC of the UID followed by every record except the HMAC itself, encoded
+// as an NDEF message in tag order
+fn content_hmac(secret: &str, uid: &[u8], records: &[ndef::Record]) -> Hmac<Sha256> {
+    let signed: Vec<ndef::Record> = records
+        .iter()
+        .filter(|r| !r.is_external(HMAC_EXTERNAL_TYPE))
+        .cloned()
+        .collect();
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(uid);
+    mac.update(&ndef::encode_message(&signed));
+    mac
+}
+
+/// ECDSA verification of the 32 byte READ_SIG response (r then s, big
+/// endian) over the UID. The UID is used as is rather than hashed.
+pub fn originality_signature_valid(uid: &[u8], signature: &[u8; 32]) -> bool {
+    signature_valid(&NXP_PUBLIC_KEY, uid, signature)
+}
+
+fn signature_valid(public_key: &Point, uid: &[u8], signature: &[u8; 32]) -> bool {
+    if uid.len() > 16 {
+        return false;
+    }
+    let r = u128::from_be_bytes(signature[..16].try_into().unwrap());
+    let s = u128::from_be_bytes(signature[16..].try_into().unwrap());
+    if r == 0 || r >= N || s == 0 || s >= N {
+        return false;
+    }
+
+    let e = uid.iter().fold(0u128, |acc, &b| (acc << 8) | b as u128) % N;
+    let w = inv_mod(s, N);
+    let u1 = mul_mod(e, w, N);
+    let u2 = mul_mod(r, w, N);
+
+    let point = G.mul(u1).add(&public_key.mul(u2));
+    match point.to_affine() {
+        Some((x, _)) => x % N == r,
+        None => false,
+    }
+}
+
+// u128 arithmetic mod m, for any m < 2^128 and a, b < m
+
+fn add_mod(a: u128, b: u128, m: u128) -> u128 {
+    let (sum, overflow) = a.overflowing_add(b);
+    if overflow || sum >= m {
+        sum.wrapping_sub(m)
+    } else {
+        sum
+    }
+}
+
+fn sub_mod(a: u128, b: u128, m: u128) -> u128 {
+    if a >= b {
+        a - b
+    } else {
+        a.wrapping_sub(b).wrapping_add(m)
+    }
+}
+
+// double and add, there's no u256 to multiply into
+fn mul_mod(a: u128, b: u128, m: u128) -> u128 {
+    let mut result = 0;
+    for bit in (0..128).rev() {
+        result = add_mod(result, result, m);
+        if (b >> bit) & 1 == 1 {
+            result = add_mod(result, a, m);
+        }
+    }
+    result
+}
+
+// Fermat's little theorem, m is prime
+fn inv_mod(a: u128, m: u128) -> u128 {
+    let mut result = 1;
+    let mut base = a;
+    let mut exponent = m - 2;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul_mod(result, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// Curve point in Jacobian coordinates (x / z², y / z³), z = 0 is the
+/// point at infinity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Point {
+    x: u128,
+    y: u128,
+    z: u128,
+}
+
+const INFINITY: Point = Point { x: 1, y: 1, z: 0 };
+
+impl Point {
+    fn to_affine(self) -> Option<(u128, u128)> {
+        if self.z == 0 {
+            return None;
+        }
+        let z_inv = inv_mod(self.z, P);
+        let z_inv2 = mul_mod(z_inv, z_inv, P);
+        let z_inv3 = mul_mod(z_inv2, z_inv, P);
+        Some((mul_mod(self.x, z_inv2, P), mul_mod(self.y, z_inv3, P)))
+    }
+
+    // dbl-2001-b, for a = -3
+    fn double(&self) -> Point {
+        if self.z == 0 || self.y == 0 {
+            return INFINITY;
+        }
+        let mul = |a, b| mul_mod(a, b, P);
+        let add = |a, b| add_mod(a, b, P);
+        let sub = |a, b| sub_mod(a, b, P);
+
+        let delta = mul(self.z, self.z);
+        let gamma = mul(self.y, self.y);
+        let beta = mul(self.x, gamma);
+        let t = mul(sub(self.x, delta), add(self.x, delta));
+        let alpha = add(add(t, t), t);
+
+        let beta4 = add(add(beta, beta), add(beta, beta));
+        let x = sub(mul(alpha, alpha), add(beta4, beta4));
+        let yz = add(self.y, self.z);
+        let z = sub(sub(mul(yz, yz), gamma), delta);
+        let gamma2 = mul(gamma, gamma);
+        let gamma2_8 = add(
+            add(add(gamma2, gamma2), add(gamma2, gamma2)),
+            add(add(gamma2, gamma2), add(gamma2, gamma2)),
+        );
+        let y = sub(mul(alpha, sub(beta4, x)), gamma2_8);
+        Point { x, y, z }
+    }
+
+    // add-2007-bl
+    fn add(&self, other: &Point) -> Point {
+        if self.z == 0 {
+            return *other;
+        }
+        if other.z == 0 {
+            return *self;
+        }
+        let mul = |a, b| mul_mod(a, b, P);
+        let add = |a, b| add_mod(a, b, P);
+        let sub = |a, b| sub_mod(a, b, P);
+
+        let z1z1 = mul(self.z, self.z);
+        let z2z2 = mul(other.z, other.z);
+        let u1 = mul(self.x, z2z2);
+        let u2 = mul(other.x, z1z1);
+        let s1 = mul(mul(self.y, other.z), z2z2);
+        let s2 = mul(mul(other.y, self.z), z1z1);
+
+        let h = sub(u2, u1);
+        let r = add(sub(s2, s1), sub(s2, s1));
+        if h == 0 {
+            return if r == 0 { self.double() } else { INFINITY };
+        }
+
+        let h2 = add(h, h);
+        let i = mul(h2, h2);
+        let j = mul(h, i);
+        let v = mul(u1, i);
+
+        let x = sub(sub(mul(r, r), j), add(v, v));
+        let s1j = mul(s1, j);
+        let y = sub(mul(r, sub(v, x)), add(s1j, s1j));
+        let z12 = add(self.z, other.z);
+        let z = mul(sub(sub(mul(z12, z12), z1z1), z2z2), h);
+        Point { x, y, z }
+    }
+
+    fn mul(&self, scalar: u128) -> Point {
+        let mut result = INFINITY;
+        for bit in (0..128).rev() {
+            result = result.double();
+            if (scalar >> bit) & 1 == 1 {
+                result = result.add(self);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // secp128r1 b, only needed to check points are on the curve
+    const B: u128 = 0xE87579C11079F43DD824993C2CEE5ED3;
+
+    // Key pair and signature over UID 04A1B2C3D4E5F6 made with an
+    // independent (affine, big integer) implementation, private key
+    // 2B7E151628AED2A6ABF7158809CF4F3C and nonce 3243F6A8885A308D313198A2E0370734
+    const TEST_KEY: Point = Point {
+        x: 0xDCA0B4C63696626B82D866EE47497BC7,
+        y: 0x66F35E6C9EAD08A33DD9B931CAAFB613,
+        z: 1,
+    };
+    const TEST_UID: [u8; 7] = [0x04, 0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6];
+    const TEST_SIGNATURE: [u8; 32] = [
+        0x2C, 0x07, 0x7F, 0xC5, 0x99, 0x72, 0x62, 0x0C, 0x25, 0x76, 0xE0, 0xB4, 0x1D, 0xB3, 0x58,
+        0x8B, 0xA1, 0xD8, 0x99, 0x50, 0x5B, 0x6A, 0x5F, 0x52, 0xA1, 0xF4, 0x23, 0x48, 0x5D, 0x24,
+        0xC2, 0x10,
+    ];
+
+    fn on_curve(point: &Point) -> bool {
+        let Some((x, y)) = point.to_affine() else {
+            return false;
+        };
+        let x3 = mul_mod(mul_mod(x, x, P), x, P);
+        let ax = mul_mod(P - 3, x, P);
+        mul_mod(y, y, P) == add_mod(add_mod(x3, ax, P), B, P)
+    }
+
+    #[test]
+    fn curve_constants() {
+        assert!(on_curve(&G));
+        assert!(on_curve(&NXP_PUBLIC_KEY));
+        assert!(on_curve(&TEST_KEY));
+        assert_eq!(G.mul(N).z, 0);
+        assert_eq!(G.mul(N - 1).to_affine(), Some((G.x, P - G.y)));
+    }
+
+    #[test]
+    fn known_answer() {
+        assert!(signature_valid(&TEST_KEY, &TEST_UID, &TEST_SIGNATURE));
+    }
+
+    #[test]
+    fn tampered_signature_fails() {
+        for byte in [0, 15, 16, 31] {
+            let mut signature = TEST_SIGNATURE;
+            signature[byte] ^= 0x01;
+            assert!(!signature_valid(&TEST_KEY, &TEST_UID, &signature));
+        }
+
+        let mut uid = TEST_UID;
+        uid[6] ^= 0x01;
+        assert!(!signature_valid(&TEST_KEY, &uid, &TEST_SIGNATURE));
+        assert!(!signature_valid(
+            &NXP_PUBLIC_KEY,
+            &TEST_UID,
+            &TEST_SIGNATURE
+        ));
+        assert!(!signature_valid(&TEST_KEY, &TEST_UID, &[0; 32]));
+    }
+
+    #[test]
+    fn hmac_known_answer() {
+        // HMAC-SHA256 keyed with "secret" of the UID then the NDEF message
+        // for a text record "3", as tags already out there were written
+        let record = hmac_record("secret", &TEST_UID, &[ndef::Record::text("en", "3")]);
+        assert!(record.is_external(HMAC_EXTERNAL_TYPE));
+        assert_eq!(
+            record.payload,
+            [
+                0x1C, 0x17, 0x53, 0x0E, 0xE0, 0xD7, 0xF4, 0x2C, 0xEA, 0x84, 0x81, 0xBC, 0xCD, 0x10,
+                0xB1, 0x8E, 0x0A, 0xDD, 0xBC, 0x78, 0xE7, 0x5A, 0x6A, 0xCE, 0x71, 0xF0, 0xCC, 0x11,
+                0xF6, 0x4C, 0xC9, 0xE2,
+            ]
+        );
+    }
+
+    #[test]
+    fn changed_contents_fail_hmac() {
+        let records = vec![ndef::Record::text("en", "3")];
+        let hmac = hmac_record("secret", &TEST_UID, &records);
+        let evidence = |records| TagEvidence {
+            uid: &TEST_UID,
+            signature: None,
+            records: Some(records),
+            fish_from_uid: false,
+        };
+
+        let changed = vec![ndef::Record::text("en", "4"), hmac.clone()];
+        assert!(verify_hmac(&evidence(&changed), "secret").is_err());
+
+        // copied onto another tag
+        let copied = vec![records[0].clone(), hmac.clone()];
+        let mut other_uid = evidence(&copied);
+        other_uid.uid = &[0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
+        assert!(verify_hmac(&other_uid, "secret").is_err());
+
+        // cut short
+        let mut short = hmac.clone();
+        short.payload.truncate(16);
+        assert!(verify_hmac(&evidence(&[records[0].clone(), short]), "secret").is_err());
+    }
+
+    #[test]
+    fn secret_needs_hmac_unless_from_uid() {
+        let records = vec![ndef::Record::text("en", "3")];
+        let mut signed = records.clone();
+        signed.push(hmac_record("secret", &TEST_UID, &records));
+
+        let evidence = |records, fish_from_uid| TagEvidence {
+            uid: &TEST_UID,
+            signature: None,
+            records,
+            fish_from_uid,
+        };
+        assert_eq!(
+            verify_hmac(&evidence(Some(&signed), false), "secret"),
+            Ok(())
+        );
+        assert!(verify_hmac(&evidence(Some(&signed), false), "other").is_err());
+        assert!(verify_hmac(&evidence(Some(&records), false), "secret").is_err());
+        // raw ASCII and legacy tags have no records to carry one
+        assert!(verify_hmac(&evidence(None, false), "secret").is_err());
+        assert_eq!(verify_hmac(&evidence(None, true), "secret"), Ok(()));
+    }
+}
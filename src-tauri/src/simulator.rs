@@ -0,0 +1,188 @@
+//! Simulated scanner, for running the whole app without a PN532 (on a
+//! laptop, or a build without the `pn532` feature). Every fish in the
+//! catalog gets a simulated NTAG215, written the way `write_fish_tag` would
+//! write it, and every so often a random one is held to the reader for a few
+//! seconds and taken away again. The tags go through the same decoders and
+//! checks as real ones, and `write_fish_tag` writes whichever one comes along
+//! next.
+//!
+//! Simulated tags have no originality signature, so they fail verification.
+
+use rand::Rng;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::catalog::FishInfo;
+use crate::config::TagFormat;
+use crate::decoder::TagRead;
+use crate::ndef;
+use crate::reader::TagHandler;
+use crate::scanner::{ReadOutcome, TagReader};
+use crate::uid_registry::format_uid;
+use crate::wear::ReadStats;
+
+const NTAG215_DATA_AREA: usize = 496;
+
+/// UID of a simulated tag with nothing on it, one that isn't any fish
+pub const BLANK_UID: [u8; 7] = [0x04, b'S', b'U', 0x00, 0x00, 0x00, 0x01];
+
+// How long between tags, and how long each is held to the reader
+const IDLE_TIME: Duration = Duration::from_secs(8);
+const PRESENT_TIME: Duration = Duration::from_secs(3);
+
+struct SimulatedTag {
+    uid: Vec<u8>,
+    /// Memory dump from page 0, as `Pn532::read_memory` returns it
+    memory: Vec<u8>,
+    /// The NFC counter
+    reads: u32,
+}
+
+pub struct SimulatedReader {
+    handler: TagHandler,
+    tags: Vec<SimulatedTag>,
+    blank: Vec<SimulatedTag>,
+    /// Index into `tags` of the one held to the reader
+    present: Option<usize>,
+    next_change: Instant,
+}
+
+impl SimulatedReader {
+    /// Simulated tags for `fish`, in the kiosk's default `format`
+    pub fn new(handler: TagHandler, fish: &[FishInfo], format: TagFormat) -> Self {
+        let tags = fish
+            .iter()
+            .map(|fish| {
+                let uid = simulated_uid(fish.id);
+                let mut memory = header(&uid);
+                match handler.encode(&uid, fish, format, NTAG215_DATA_AREA) {
+                    Ok(user_memory) => memory.extend_from_slice(&user_memory),
+                    Err(e) => eprintln!(
+                        "Scanner: Fish ID '{}' - Simulated tag left blank - {}",
+                        fish.id, e
+                    ),
+                }
+                memory.resize(ndef::USER_MEMORY_OFFSET + NTAG215_DATA_AREA, 0);
+                SimulatedTag {
+                    uid,
+                    memory,
+                    reads: 0,
+                }
+            })
+            .collect();
+
+        Self {
+            handler,
+            tags,
+            blank: Vec::new(),
+            present: None,
+            next_change: Instant::now() + IDLE_TIME,
+        }
+    }
+
+    /// Adds a tag with nothing on it, which is never held to the reader by
+    /// `detect`
+    pub fn add_blank_tag(&mut self, uid: Vec<u8>) {
+        let mut memory = header(&uid);
+        memory.resize(ndef::USER_MEMORY_OFFSET + NTAG215_DATA_AREA, 0);
+        self.blank.push(SimulatedTag {
+            uid,
+            memory,
+            reads: 0,
+        });
+    }
+
+    fn tag(&mut self, uid: &[u8]) -> Option<&mut SimulatedTag> {
+        self.tags
+            .iter_mut()
+            .chain(&mut self.blank)
+            .find(|tag| tag.uid == uid)
+    }
+}
+
+impl TagReader for SimulatedReader {
+    fn detect(&mut self, timeout_ms: u64) -> Result<Option<Vec<u8>>, String> {
+        let now = Instant::now();
+        if now >= self.next_change && !self.tags.is_empty() {
+            self.present = match self.present {
+                Some(_) => {
+                    self.next_change = now + IDLE_TIME;
+                    None
+                }
+                None => {
+                    let index = rand::thread_rng().gen_range(0..self.tags.len());
+                    println!(
+                        "Scanner: Simulating tag UID {} held to the reader",
+                        format_uid(&self.tags[index].uid)
+                    );
+                    self.next_change = now + PRESENT_TIME;
+                    Some(index)
+                }
+            };
+        }
+
+        match self.present {
+            Some(index) => Ok(Some(self.tags[index].uid.clone())),
+            None => {
+                // like the real reader, wait for a tag up to the timeout
+                let wait = self.next_change.saturating_duration_since(now);
+                thread::sleep(wait.min(Duration::from_millis(timeout_ms)));
+                Ok(None)
+            }
+        }
+    }
+
+    fn read(&mut self, uid: &[u8]) -> ReadOutcome {
+        let Some(tag) = self.tag(uid) else {
+            return ReadOutcome::Unknown;
+        };
+        tag.reads += 1;
+        let (memory, reads) = (tag.memory.clone(), tag.reads);
+
+        self.handler
+            .record_wear(uid, ReadStats::default(), Some(reads));
+        self.handler.read(&TagRead::new(uid, &memory), || None)
+    }
+
+    fn write(&mut self, uid: &[u8], fish: &FishInfo, format: TagFormat) -> Result<(), String> {
+        let user_memory = self.handler.encode(uid, fish, format, NTAG215_DATA_AREA)?;
+        let tag = self.tag(uid).ok_or("Tag went away")?;
+        tag.memory.truncate(ndef::USER_MEMORY_OFFSET);
+        tag.memory.extend_from_slice(&user_memory);
+        tag.memory
+            .resize(ndef::USER_MEMORY_OFFSET + NTAG215_DATA_AREA, 0);
+        Ok(())
+    }
+}
+
+// 7 byte NXP-style UID, "SM" for simulated followed by the fish id
+pub fn simulated_uid(fish_id: u32) -> Vec<u8> {
+    let mut uid = vec![0x04, b'S', b'M'];
+    uid.extend_from_slice(&fish_id.to_be_bytes());
+    uid
+}
+
+// Pages 0-3 of an NTAG215: UID with its check bytes, lock bytes (unlocked)
+// and the capability container
+fn header(uid: &[u8]) -> Vec<u8> {
+    let bcc0 = 0x88 ^ uid[0] ^ uid[1] ^ uid[2];
+    let bcc1 = uid[3] ^ uid[4] ^ uid[5] ^ uid[6];
+    vec![
+        uid[0],
+        uid[1],
+        uid[2],
+        bcc0,
+        uid[3],
+        uid[4],
+        uid[5],
+        uid[6],
+        bcc1,
+        0x48,
+        0x00,
+        0x00,
+        0xE1,
+        0x10,
+        (NTAG215_DATA_AREA / 8) as u8,
+        0x00,
+    ]
+}
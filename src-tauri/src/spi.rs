@@ -0,0 +1,403 @@
+//! What the PN532 driver talks to. On the kiosk that's the SPI bus of the
+//! HAT (with the `pn532` feature, on Linux), but the driver only needs something that
+//! moves bytes, so a field capture can be played back to it instead:
+//!
+//!   - `CaptureTransport` writes every transfer to a file as it goes through
+//!   - `ReplayTransport` answers the driver from one of those files, so a
+//!     reader misbehaving at the exhibit can be run again without hardware
+//!
+//! Captures are text, a line for what was sent (`>`) then one for what came
+//! back (`<`), with the seconds since capturing started:
+//!
+//!   0.002031 > 01 00 00 FF 05 FB D4 14 01 14 01 02 00
+//!   0.002031 < 00 00 00 00 00 00 00 00 00 00 00 00 00
+//!   0.017544 > 02 00
+//!   0.017544 < 00 01
+//!
+//! Bytes are as the PN532 sees them, after undoing the bit order swap.
+
+use std::path::Path;
+
+use crate::pn532::SPI_STATREAD;
+
+const SPI_NOT_READY: u8 = 0x00;
+
+pub trait Transport: Send {
+    /// Clocks `data` out with chip select held low, replacing it with what
+    /// came back
+    fn transfer(&mut self, data: &mut [u8]);
+
+    /// Resets and wakes the PN532, before the first command
+    fn power_on(&mut self) {}
+}
+
+#[cfg(all(feature = "pn532", target_os = "linux"))]
+pub use hardware::{CaptureTransport, SpiTransport};
+
+#[cfg(all(feature = "pn532", target_os = "linux"))]
+mod hardware {
+    use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
+    use spidev::{SpiModeFlags, Spidev, SpidevOptions, SpidevTransfer};
+    use std::fs::File;
+    use std::io::{LineWriter, Write};
+    use std::path::Path;
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+
+    use super::{hex, Transport};
+
+    // Waveshare PN532 NFC HAT pinout:
+    //   RSTPDN → BCM 20
+    //   NSS    → BCM 4 (directly to D4 (BCM), not directly to the Pi's dedicated SPI0 CE0 pin)
+    const RESET_PIN: u32 = 20;
+    const NSS_PIN: u32 = 4;
+
+    // PN532 SPI uses LSB-first bit order, but Linux SPI is MSB-first
+    // so we need to reverse the bits before and after sending/receiving data
+    fn reverse_bit(b: u8) -> u8 {
+        let mut result: u8 = 0;
+        let mut num = b;
+        for _ in 0..8 {
+            result <<= 1;
+            result += num & 1;
+            num >>= 1;
+        }
+        result
+    }
+
+    /// The HAT on the Pi's SPI bus
+    pub struct SpiTransport {
+        spi: Spidev,
+        cs: LineHandle,
+        reset: LineHandle,
+    }
+
+    impl SpiTransport {
+        pub fn open() -> Result<Self, String> {
+            let mut spi =
+                Spidev::open("/dev/spidev0.0").map_err(|e| format!("Failed to open SPI: {}", e))?;
+
+            spi.configure(
+                &SpidevOptions::new()
+                    .bits_per_word(8)
+                    .max_speed_hz(1_000_000)
+                    .mode(SpiModeFlags::SPI_MODE_0)
+                    .build(),
+            )
+            .map_err(|e| format!("Failed to configure SPI: {}", e))?;
+
+            let mut chip =
+                Chip::new("/dev/gpiochip0").map_err(|e| format!("Failed to open GPIO: {}", e))?;
+
+            let cs = chip
+                .get_line(NSS_PIN)
+                .map_err(|e| format!("Failed to get NSS pin: {}", e))?
+                .request(LineRequestFlags::OUTPUT, 1, "pn532-cs")
+                .map_err(|e| format!("Failed to request CS: {}", e))?;
+
+            let reset = chip
+                .get_line(RESET_PIN)
+                .map_err(|e| format!("Failed to get reset pin: {}", e))?
+                .request(LineRequestFlags::OUTPUT, 1, "pn532-reset")
+                .map_err(|e| format!("Failed to request reset: {}", e))?;
+
+            Ok(Self { spi, cs, reset })
+        }
+
+        fn hardware_reset(&mut self) {
+            self.reset.set_value(1).ok();
+            sleep(Duration::from_millis(100));
+            self.reset.set_value(0).ok();
+            sleep(Duration::from_millis(500));
+            self.reset.set_value(1).ok();
+            sleep(Duration::from_millis(100));
+        }
+
+        fn cs_low(&self) {
+            self.cs.set_value(0).ok();
+        }
+
+        fn cs_high(&self) {
+            self.cs.set_value(1).ok();
+        }
+
+        fn wakeup(&mut self) {
+            sleep(Duration::from_millis(1000));
+            self.cs_low();
+            sleep(Duration::from_millis(2));
+            let mut data = [0x00u8];
+            data[0] = reverse_bit(data[0]);
+            let tx = data.to_vec();
+            let mut transfer = SpidevTransfer::read_write(&tx, &mut data);
+            self.spi.transfer(&mut transfer).ok();
+            sleep(Duration::from_millis(1000));
+            self.cs_high();
+        }
+    }
+
+    impl Transport for SpiTransport {
+        fn transfer(&mut self, data: &mut [u8]) {
+            self.cs_low();
+            sleep(Duration::from_millis(1));
+
+            for b in data.iter_mut() {
+                *b = reverse_bit(*b);
+            }
+
+            let tx = data.to_vec();
+            let mut transfer = SpidevTransfer::read_write(&tx, data);
+            self.spi.transfer(&mut transfer).ok();
+
+            for b in data.iter_mut() {
+                *b = reverse_bit(*b);
+            }
+
+            sleep(Duration::from_millis(1));
+            self.cs_high();
+        }
+
+        fn power_on(&mut self) {
+            self.hardware_reset();
+            self.wakeup();
+        }
+    }
+
+    /// Passes transfers through to another transport, writing them to a
+    /// capture file as well
+    pub struct CaptureTransport {
+        inner: Box<dyn Transport>,
+        /// `None` after a failed write, so the reader keeps going without it
+        file: Option<LineWriter<File>>,
+        started: Instant,
+    }
+
+    impl CaptureTransport {
+        pub fn create(inner: Box<dyn Transport>, path: &Path) -> Result<Self, String> {
+            let file = File::create(path)
+                .map_err(|e| format!("Failed to create {} - {}", path.display(), e))?;
+            Ok(Self {
+                inner,
+                file: Some(LineWriter::new(file)),
+                started: Instant::now(),
+            })
+        }
+    }
+
+    impl Transport for CaptureTransport {
+        fn transfer(&mut self, data: &mut [u8]) {
+            let sent = hex(data);
+            self.inner.transfer(data);
+
+            let Some(file) = &mut self.file else {
+                return;
+            };
+            let time = self.started.elapsed().as_secs_f64();
+            let written = writeln!(file, "{:.6} > {}", time, sent)
+                .and_then(|_| writeln!(file, "{:.6} < {}", time, hex(data)));
+            if let Err(e) = written {
+                eprintln!("Scanner: Stopped capturing SPI traffic - {}", e);
+                self.file = None;
+            }
+        }
+
+        fn power_on(&mut self) {
+            self.inner.power_on();
+        }
+    }
+}
+
+/// What was sent and what came back
+type Transfer = (Vec<u8>, Vec<u8>);
+
+/// Answers the driver from a capture file
+pub struct ReplayTransport {
+    transfers: Vec<Transfer>,
+    next: usize,
+    finished: bool,
+}
+
+impl ReplayTransport {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let capture = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {} - {}", path.display(), e))?;
+        let transfers =
+            parse_capture(&capture).map_err(|e| format!("{} - {}", path.display(), e))?;
+        Ok(Self {
+            transfers,
+            next: 0,
+            finished: false,
+        })
+    }
+
+    fn finish(&mut self, data: &mut [u8], why: &str) {
+        if !self.finished {
+            println!(
+                "Scanner: Replay finished after {} transfers - {}",
+                self.next, why
+            );
+            self.finished = true;
+        }
+        data.fill(0);
+    }
+}
+
+impl Transport for ReplayTransport {
+    // Waiting for the PN532 is timed, so the driver won't poll its status
+    // exactly as often as it did when capturing. Status polls are matched up
+    // loosely, anything else has to be sent exactly as captured.
+    fn transfer(&mut self, data: &mut [u8]) {
+        if self.finished {
+            data.fill(0);
+            return;
+        }
+
+        let is_status = |tx: &[u8]| tx.first() == Some(&SPI_STATREAD);
+        let mut next = self.next;
+        // polled less than when capturing, skip the extra polls
+        if !is_status(data) {
+            while self
+                .transfers
+                .get(next)
+                .is_some_and(|(tx, _)| is_status(tx))
+            {
+                next += 1;
+            }
+        }
+
+        match self.transfers.get(next) {
+            Some((tx, rx)) if tx == data => {
+                data.copy_from_slice(rx);
+                self.next = next + 1;
+            }
+            // polled more than when capturing, it isn't ready yet
+            _ if is_status(data) && data.len() > 1 => data[1] = SPI_NOT_READY,
+            Some((tx, _)) => {
+                let why = format!("sent {} where the capture has {}", hex(data), hex(tx));
+                self.finish(data, &why);
+            }
+            None => self.finish(data, "end of capture"),
+        }
+    }
+}
+
+fn parse_capture(capture: &str) -> Result<Vec<Transfer>, String> {
+    let mut lines = capture
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'));
+
+    let mut transfers = Vec::new();
+    while let Some((i, sent)) = lines.next() {
+        let tx = parse_line(sent, ">").map_err(|e| format!("Line {} - {}", i + 1, e))?;
+        let Some((i, received)) = lines.next() else {
+            return Err(format!("Line {} - Nothing received for it", i + 1));
+        };
+        let rx = parse_line(received, "<").map_err(|e| format!("Line {} - {}", i + 1, e))?;
+        if rx.len() != tx.len() {
+            return Err(format!(
+                "Line {} - Length doesn't match what was sent",
+                i + 1
+            ));
+        }
+        transfers.push((tx, rx));
+    }
+    Ok(transfers)
+}
+
+// "<time> <direction> <hex bytes...>"
+fn parse_line(line: &str, direction: &str) -> Result<Vec<u8>, String> {
+    let mut fields = line.split_whitespace();
+    let _time = fields.next();
+    if fields.next() != Some(direction) {
+        return Err(format!("Expected '{}'", direction));
+    }
+    fields
+        .map(|byte| {
+            u8::from_str_radix(byte, 16).map_err(|_| format!("'{}' isn't a hex byte", byte))
+        })
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::KioskConfig;
+    use crate::pn532::Pn532;
+    use crate::reader::{Pn532Reader, TagHandler};
+    use crate::scanner::{ReadOutcome, TagReader};
+    use crate::uid_registry::UidRegistry;
+    use std::sync::{Arc, Mutex};
+
+    const READ_NTAG213: &str = include_str!("../tests/fixtures/read_ntag213.txt");
+
+    fn replay(capture: &str) -> ReplayTransport {
+        ReplayTransport {
+            transfers: parse_capture(capture).unwrap(),
+            next: 0,
+            finished: false,
+        }
+    }
+
+    #[test]
+    fn replayed_read_decodes_the_fish() {
+        let mut pn532 = Pn532::new(Box::new(replay(READ_NTAG213)));
+        assert!(pn532.sam_config());
+
+        // nothing enrolled, the fish has to come off the tag
+        let registry = UidRegistry::load(&std::env::temp_dir().join("spi-no-registry.json"));
+        let handler = TagHandler::new(KioskConfig::default(), Arc::new(Mutex::new(registry)), None);
+        let mut reader = Pn532Reader::new(pn532, handler);
+
+        let uid = reader.detect(1000).unwrap().unwrap();
+        assert_eq!(uid, [0x04, 0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6]);
+        match reader.read(&uid) {
+            ReadOutcome::Fish(tag) => {
+                assert_eq!((tag.fish_id, tag.language.as_deref()), (3, Some("en")))
+            }
+            outcome => panic!("expected fish 3, got {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn capture_skips_comments_and_blank_lines() {
+        let capture = "# comment\n\n0.01 > 02 00\n0.01 < 00 01\n";
+        assert_eq!(
+            parse_capture(capture),
+            Ok(vec![(vec![0x02, 0x00], vec![0x00, 0x01])])
+        );
+    }
+
+    #[test]
+    fn capture_errors() {
+        let error = |capture| parse_capture(capture).unwrap_err();
+        assert_eq!(
+            error("# comment\n0.01 < 02 00\n0.01 < 00 01"),
+            "Line 2 - Expected '>'"
+        );
+        assert_eq!(error("0.01 > 02 00\n0.01 > 00 01"), "Line 2 - Expected '<'");
+        assert_eq!(
+            error("0.01 > 02 zz\n0.01 < 00 01"),
+            "Line 1 - 'zz' isn't a hex byte"
+        );
+        assert_eq!(
+            error("0.01 > 02 00\n0.01 < 00 1AB"),
+            "Line 2 - '1AB' isn't a hex byte"
+        );
+        assert_eq!(
+            error("0.01 > 02 00\n0.01 < 00 01\n0.02 > 02 00\n"),
+            "Line 3 - Nothing received for it"
+        );
+        assert_eq!(
+            error("0.01 > 02 00\n0.01 < 00"),
+            "Line 2 - Length doesn't match what was sent"
+        );
+    }
+}
@@ -0,0 +1,347 @@
+//! Fish tag NDEF contents: reading the fish (and extras) out of a tag's
+//! records, and encoding them for writing.
+
+use crate::catalog::FishInfo;
+use crate::catch::TagCatch;
+use crate::config::TagFormat;
+use crate::fish_record;
+use crate::i18n::Language;
+use crate::ndef;
+use crate::signature;
+
+// Record types the optional per-tag catch details can be stored as, both
+// with a JSON payload (see `TagCatch`)
+const CATCH_MIME_TYPE: &str = "application/json";
+const CATCH_EXTERNAL_TYPE: &str = "sno-port.example:catch";
+
+// Record type of a whole fish in the compact format, see `fish_record`
+const FISH_EXTERNAL_TYPE: &str = "sno-port.example:fish";
+
+// What to try putting on a self-describing tag, most first: translations
+// and whether to include the blurb
+const FISH_RECORD_CONTENTS: [(&[Language], bool); 6] = [
+    (&[Language::Fr, Language::Es], true),
+    (&[Language::Fr], true),
+    (&[], true),
+    (&[Language::Fr, Language::Es], false),
+    (&[Language::Fr], false),
+    (&[], false),
+];
+
+#[derive(Debug, Clone)]
+pub struct FishTag {
+    pub fish_id: u32,
+    /// Language code of the NDEF text record the id came from, if any
+    pub language: Option<String>,
+    /// Details of this particular prop fish from a second NDEF record
+    pub catch: Option<TagCatch>,
+    /// Full fish record for self-describing tags, only shown if the catalog
+    /// doesn't have the fish. Boxed, it's big and usually not there.
+    pub fish: Option<Box<FishInfo>>,
+}
+
+impl FishTag {
+    /// A tag known only by its id, e.g. from the UID registry
+    pub fn from_id(fish_id: u32) -> Self {
+        Self {
+            fish_id,
+            language: None,
+            catch: None,
+            fish: None,
+        }
+    }
+
+    /// Reads the fish id from an NTAG memory dump starting at page 0.
+    /// Tags hold an NDEF text record whose text is the fish id, e.g. "3"
+    /// with language "en", or a URI record matching `url_pattern` (see
+    /// `fish_id_from_url`).
+    pub fn from_ndef(memory: &[u8], url_pattern: &str) -> Option<Self> {
+        if let Some(records) = ndef::parse_tag_memory(memory) {
+            let fish = fish_record(&records).map(Box::new);
+            let tag = |fish_id, language| FishTag {
+                fish_id,
+                language,
+                catch: catch_record(&records),
+                fish: fish.clone(),
+            };
+
+            let from_text = records
+                .iter()
+                .filter_map(ndef::Record::as_text)
+                .find_map(|text| Some((parse_digits(text.text.as_bytes())?, text.language)));
+            if let Some((fish_id, language)) = from_text {
+                return Some(tag(fish_id, Some(language)));
+            }
+
+            let from_url = records
+                .iter()
+                .filter_map(ndef::Record::as_uri)
+                .find_map(|url| fish_id_from_url(&url, url_pattern));
+            if let Some(fish_id) = from_url {
+                return Some(tag(fish_id, None));
+            }
+
+            // self-describing tags don't strictly need a separate id record
+            if let Some(fish_id) = fish.as_ref().map(|f| f.id) {
+                return Some(tag(fish_id, None));
+            }
+        }
+
+        Self::from_legacy_memory(memory)
+    }
+
+    // Older tags were only ever matched by scanning for "en" followed by
+    // digits, keep that around for anything the NDEF parser can't make sense
+    // of (e.g. a dump cut short by a failed page read)
+    fn from_legacy_memory(memory: &[u8]) -> Option<Self> {
+        let pos = memory.windows(2).position(|w| w == b"en")?;
+        Some(FishTag {
+            fish_id: parse_digits(&memory[pos + 2..])?,
+            language: None,
+            catch: None,
+            fish: None,
+        })
+    }
+}
+
+/// Kiosk secret and tag UID to add an HMAC record with, see `signature`
+pub struct TagSigner<'a> {
+    pub secret: &'a str,
+    pub uid: &'a [u8],
+}
+
+/// User memory contents (from page 4) for a tag for `fish` in `format`.
+/// `capacity` is the tag's user memory size. For self-describing tags,
+/// translations and then the blurb are dropped until the record fits.
+pub fn encode_fish_tag(
+    fish: &FishInfo,
+    format: TagFormat,
+    url_pattern: &str,
+    signer: Option<&TagSigner>,
+    capacity: usize,
+) -> Result<Vec<u8>, String> {
+    let encode = |mut records: Vec<ndef::Record>| {
+        if let Some(signer) = signer {
+            records.push(signature::hmac_record(signer.secret, signer.uid, &records));
+        }
+        ndef::encode_tag_memory(&records, capacity)
+    };
+
+    let url = fish_url(url_pattern, fish.id);
+    match format {
+        TagFormat::Text => encode(vec![ndef::Record::text("en", &fish.id.to_string())]),
+        TagFormat::Url => encode(vec![ndef::Record::uri(&url)]),
+        TagFormat::Full => {
+            // the URI record comes first so phones still open the page
+            let mut result = Err(String::new());
+            for (languages, blurb) in FISH_RECORD_CONTENTS {
+                let record = fish_record::encode(fish, languages, blurb);
+                result = encode(vec![
+                    ndef::Record::uri(&url),
+                    ndef::Record::external(FISH_EXTERNAL_TYPE, record),
+                ]);
+                if result.is_ok() {
+                    break;
+                }
+            }
+            result
+        }
+    }
+}
+
+/// `pattern` with `{id}` replaced by the fish id
+pub fn fish_url(pattern: &str, fish_id: u32) -> String {
+    pattern.replace("{id}", &fish_id.to_string())
+}
+
+/// The fish id from a URL made from `pattern`, e.g. 3 from
+/// "https://sno-port.example/fish/3" with the default pattern. The part
+/// before the id is matched case-insensitively, and a query string,
+/// fragment or trailing slash after the pattern is ignored.
+pub fn fish_id_from_url(url: &str, pattern: &str) -> Option<u32> {
+    let (prefix, suffix) = pattern.split_once("{id}")?;
+    let head = url.get(..prefix.len())?;
+    if !head.eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+
+    let id = &url[prefix.len()..];
+    let digits = id.bytes().take_while(u8::is_ascii_digit).count();
+    let rest = id[digits..].strip_prefix(suffix)?;
+    if !(rest.is_empty() || rest.starts_with(['/', '?', '#'])) {
+        return None;
+    }
+    id[..digits].parse().ok()
+}
+
+fn fish_record(records: &[ndef::Record]) -> Option<FishInfo> {
+    let record = records.iter().find(|r| r.is_external(FISH_EXTERNAL_TYPE))?;
+
+    match fish_record::decode(&record.payload) {
+        Ok(fish) => Some(fish),
+        Err(e) => {
+            eprintln!("Scanner: Ignoring invalid fish record - {}", e);
+            None
+        }
+    }
+}
+
+fn catch_record(records: &[ndef::Record]) -> Option<TagCatch> {
+    let record = records
+        .iter()
+        .find(|r| r.is_mime(CATCH_MIME_TYPE) || r.is_external(CATCH_EXTERNAL_TYPE))?;
+
+    match serde_json::from_slice(&record.payload) {
+        Ok(catch) => Some(catch),
+        Err(e) => {
+            // the fish itself is still fine to show without the extras
+            eprintln!("Scanner: Ignoring invalid catch record - {}", e);
+            None
+        }
+    }
+}
+
+/// Leading ASCII digits as a number
+pub fn parse_digits(bytes: &[u8]) -> Option<u32> {
+    let len = bytes.iter().take_while(|b| b.is_ascii_digit()).count();
+    std::str::from_utf8(&bytes[..len]).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::{Quantity, Unit};
+
+    const URL_PATTERN: &str = "https://sno-port.example/fish/{id}";
+
+    // an NTAG213 holding `records`, from page 0
+    fn memory(records: &[ndef::Record]) -> Vec<u8> {
+        let mut memory = vec![
+            0x04, 0xA1, 0xB2, 0x9F, 0xC3, 0xD4, 0xE5, 0xF6, 0x04, 0x48, 0x00, 0x00, 0xE1, 0x10,
+            0x12, 0x00,
+        ];
+        memory.extend(ndef::encode_tag_memory(records, 144).unwrap());
+        memory
+    }
+
+    fn read(records: &[ndef::Record]) -> FishTag {
+        FishTag::from_ndef(&memory(records), URL_PATTERN).unwrap()
+    }
+
+    fn mime(mime_type: &str, payload: &str) -> ndef::Record {
+        ndef::Record {
+            tnf: ndef::TNF_MIME,
+            record_type: mime_type.as_bytes().to_vec(),
+            id: Vec::new(),
+            payload: payload.as_bytes().to_vec(),
+        }
+    }
+
+    const CATCH_JSON: &str =
+        r#"{ "weight": { "value": 23, "unit": "pounds" }, "nickname": "Big Hal" }"#;
+
+    fn big_hal() -> TagCatch {
+        TagCatch {
+            weight: Some(Quantity {
+                value: 23.0,
+                unit: Unit::Pounds,
+            }),
+            nickname: Some("Big Hal".to_string()),
+            ..TagCatch::default()
+        }
+    }
+
+    #[test]
+    fn catch_from_a_mime_record() {
+        let tag = read(&[
+            ndef::Record::text("en", "2"),
+            mime("application/json", CATCH_JSON),
+        ]);
+        assert_eq!(tag.fish_id, 2);
+        assert_eq!(tag.catch, Some(big_hal()));
+
+        let tag = read(&[
+            ndef::Record::text("en", "2"),
+            mime("Application/JSON", CATCH_JSON),
+        ]);
+        assert_eq!(tag.catch, Some(big_hal()));
+    }
+
+    #[test]
+    fn catch_from_an_external_record() {
+        let tag = read(&[
+            ndef::Record::uri("https://sno-port.example/fish/2"),
+            ndef::Record::external(CATCH_EXTERNAL_TYPE, CATCH_JSON.as_bytes().to_vec()),
+        ]);
+        assert_eq!(tag.fish_id, 2);
+        assert_eq!(tag.catch, Some(big_hal()));
+    }
+
+    #[test]
+    fn bad_catch_records_are_ignored() {
+        for payload in [r#"{ "weight": 23 "#, "not json", r#"{ "weight": "heavy" }"#] {
+            let tag = read(&[
+                ndef::Record::text("en", "2"),
+                mime("application/json", payload),
+            ]);
+            assert_eq!(tag.fish_id, 2, "{}", payload);
+            assert_eq!(tag.catch, None, "{}", payload);
+        }
+
+        // some other MIME type isn't a catch record
+        let tag = read(&[
+            ndef::Record::text("en", "2"),
+            mime("text/plain", CATCH_JSON),
+        ]);
+        assert_eq!(tag.catch, None);
+    }
+
+    #[test]
+    fn fish_url_round_trip() {
+        let url = fish_url(URL_PATTERN, 42);
+        assert_eq!(url, "https://sno-port.example/fish/42");
+        assert_eq!(fish_id_from_url(&url, URL_PATTERN), Some(42));
+
+        let pattern = "https://example.org/exhibit?fish={id}&lang=en";
+        let url = fish_url(pattern, 7);
+        assert_eq!(fish_id_from_url(&url, pattern), Some(7));
+    }
+
+    #[test]
+    fn fish_id_from_urls() {
+        let id = |url| fish_id_from_url(url, URL_PATTERN);
+        assert_eq!(id("HTTPS://SNO-PORT.example/Fish/3"), Some(3));
+        assert_eq!(id("https://sno-port.example/fish/3/"), Some(3));
+        assert_eq!(id("https://sno-port.example/fish/3?from=tag"), Some(3));
+        assert_eq!(id("https://sno-port.example/fish/3#facts"), Some(3));
+
+        assert_eq!(id("https://sno-port.example/fish/"), None);
+        assert_eq!(id("https://sno-port.example/fish/3a"), None);
+        assert_eq!(id("https://sno-port.example/fish/-3"), None);
+        assert_eq!(id("https://sno-port.example/fishy/3"), None);
+        assert_eq!(id("http://sno-port.example/fish/3"), None);
+        assert_eq!(id("https://evil.example/fish/3"), None);
+        assert_eq!(id("https://sno-port.example/fish/99999999999"), None);
+        assert_eq!(
+            fish_id_from_url("https://sno-port.example/3", "no id"),
+            None
+        );
+
+        // the part after the id has to match too
+        let pattern = "https://sno-port.example/fish/{id}.html";
+        assert_eq!(
+            fish_id_from_url("https://sno-port.example/fish/3.html", pattern),
+            Some(3)
+        );
+        assert_eq!(
+            fish_id_from_url("https://sno-port.example/fish/3.htm", pattern),
+            None
+        );
+    }
+
+    #[test]
+    fn no_catch_record() {
+        let tag = read(&[ndef::Record::text("en", "2")]);
+        assert_eq!((tag.fish_id, tag.catch), (2, None));
+    }
+}
@@ -0,0 +1,207 @@
+//! Fish looked up by tag UID instead of tag contents, for read-only tags with
+//! blank memory. Enrolled UIDs are kept in `tag_uids.json` next to
+//! `kiosk.json`, as a map of hex UID to fish id:
+//!
+//!   { "04A1B2C3D4E5F6": 3, "04112233445566": 5 }
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::json_store;
+
+pub const REGISTRY_FILE_NAME: &str = "tag_uids.json";
+
+// How many unknown UIDs to remember for staff to enrol
+const MAX_UNKNOWN: usize = 20;
+
+pub struct UidRegistry {
+    path: PathBuf,
+    entries: BTreeMap<String, u32>,
+    /// Most recent last, no duplicates
+    unknown: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct UidRegistryPayload {
+    entries: BTreeMap<String, u32>,
+    /// Recently scanned UIDs that aren't enrolled and had nothing readable on
+    /// them, most recent first
+    unknown: Vec<String>,
+}
+
+impl UidRegistry {
+    pub fn load(path: &Path) -> Self {
+        let entries = json_store::load::<BTreeMap<String, u32>>(path)
+            .into_iter()
+            .filter_map(|(uid, fish_id)| match normalize_uid(&uid) {
+                Ok(uid) => Some((uid, fish_id)),
+                Err(e) => {
+                    eprintln!("Config: {} - Ignoring '{}' - {}", path.display(), uid, e);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            path: path.to_path_buf(),
+            entries,
+            unknown: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, uid: &[u8]) -> Option<u32> {
+        self.entries.get(&format_uid(uid)).copied()
+    }
+
+    /// Enrols `uid` as fish `fish_id`, or removes it when `fish_id` is
+    /// `None`, and saves the registry file.
+    pub fn set(&mut self, uid: &str, fish_id: Option<u32>) -> Result<(), String> {
+        let uid = normalize_uid(uid)?;
+        match fish_id {
+            Some(fish_id) => {
+                self.unknown.retain(|u| *u != uid);
+                self.entries.insert(uid, fish_id);
+            }
+            None => {
+                self.entries.remove(&uid);
+            }
+        }
+        self.save()
+    }
+
+    /// Remembers a scanned UID that couldn't be matched to a fish
+    pub fn note_unknown(&mut self, uid: &[u8]) {
+        let uid = format_uid(uid);
+        self.unknown.retain(|u| *u != uid);
+        self.unknown.push(uid);
+        if self.unknown.len() > MAX_UNKNOWN {
+            self.unknown.remove(0);
+        }
+    }
+
+    pub fn payload(&self) -> UidRegistryPayload {
+        UidRegistryPayload {
+            entries: self.entries.clone(),
+            unknown: self.unknown.iter().rev().cloned().collect(),
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        json_store::save(&self.path, &self.entries)
+    }
+}
+
+/// Upper case hex without separators, e.g. "04A1B2C3D4E5F6"
+pub fn format_uid(uid: &[u8]) -> String {
+    uid.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+// Accepts the UID as staff are likely to type it, "04:a1:b2..." or
+// "04 A1 B2..." included. NTAG UIDs are 7 bytes, but 4 and 10 byte UIDs are
+// valid ISO14443A too.
+pub fn normalize_uid(uid: &str) -> Result<String, String> {
+    let hex: String = uid
+        .chars()
+        .filter(|c| !matches!(c, ':' | ' ' | '-'))
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("UID isn't hex".to_string());
+    }
+    if ![8, 14, 20].contains(&hex.len()) {
+        return Err(format!(
+            "UID has {} digits, expected 8, 14 or 20",
+            hex.len()
+        ));
+    }
+    Ok(hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("uid-registry-{}-{}", name, std::process::id()))
+            .join(REGISTRY_FILE_NAME)
+    }
+
+    fn cleanup(path: &Path) {
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn normalizes_typed_uids() {
+        for typed in [
+            "04a1b2c3d4e5f6",
+            "04:A1:b2:C3:d4:E5:f6",
+            "04 a1 b2 c3 d4 e5 f6",
+            "04-A1-B2-C3-D4-E5-F6",
+        ] {
+            assert_eq!(normalize_uid(typed).as_deref(), Ok("04A1B2C3D4E5F6"));
+        }
+        assert_eq!(normalize_uid("deadbeef").as_deref(), Ok("DEADBEEF"));
+        assert_eq!(
+            normalize_uid("04A1B2C3D4E5F60718293A"),
+            Err("UID has 22 digits, expected 8, 14 or 20".to_string())
+        );
+        assert_eq!(
+            normalize_uid("04A1B2C3D4E5FG"),
+            Err("UID isn't hex".to_string())
+        );
+        assert!(normalize_uid("").is_err());
+    }
+
+    #[test]
+    fn set_overrides_and_removes() {
+        let path = path("set");
+        let mut registry = UidRegistry::load(&path);
+        let uid = [0x04, 0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6];
+
+        registry.set("04:a1:b2:c3:d4:e5:f6", Some(3)).unwrap();
+        assert_eq!(registry.get(&uid), Some(3));
+        registry.set("04A1B2C3D4E5F6", Some(5)).unwrap();
+        assert_eq!(registry.get(&uid), Some(5));
+        assert_eq!(registry.payload().entries.len(), 1);
+
+        // saved as it goes
+        assert_eq!(UidRegistry::load(&path).get(&uid), Some(5));
+
+        registry.set("04a1b2c3d4e5f6", None).unwrap();
+        assert_eq!(registry.get(&uid), None);
+        assert_eq!(UidRegistry::load(&path).get(&uid), None);
+        assert!(registry.set("nope", Some(1)).is_err());
+        cleanup(&path);
+    }
+
+    #[test]
+    fn unknown_uids_are_capped() {
+        let path = path("unknown");
+        let mut registry = UidRegistry::load(&path);
+        let uid = |i: usize| vec![0x04, 0x00, 0x00, i as u8];
+
+        for i in 0..MAX_UNKNOWN + 5 {
+            registry.note_unknown(&uid(i));
+        }
+        let unknown = registry.payload().unknown;
+        assert_eq!(unknown.len(), MAX_UNKNOWN);
+        // most recent first, the oldest five gone
+        assert_eq!(unknown.first(), Some(&format_uid(&uid(MAX_UNKNOWN + 4))));
+        assert_eq!(unknown.last(), Some(&format_uid(&uid(5))));
+
+        // seen again moves it to the front rather than adding it twice
+        registry.note_unknown(&uid(5));
+        let unknown = registry.payload().unknown;
+        assert_eq!(unknown.len(), MAX_UNKNOWN);
+        assert_eq!(unknown.first(), Some(&format_uid(&uid(5))));
+        assert_eq!(unknown.last(), Some(&format_uid(&uid(6))));
+
+        // and enrolling it takes it off the list
+        registry.set(&format_uid(&uid(5)), Some(2)).unwrap();
+        assert!(!registry.payload().unknown.contains(&format_uid(&uid(5))));
+        cleanup(&path);
+    }
+}
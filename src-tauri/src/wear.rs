@@ -0,0 +1,290 @@
+//! Wear statistics for tags, so tags that are dying inside a fish get
+//! replaced before they fail mid-visit. For each UID the scanner records how
+//! its reads went (failed block reads, retries, frames with bad checksums)
+//! along with the NTAG NFC counter, which counts reads by any reader, phones
+//! included. Kept in `tag_wear.json` next to `kiosk.json`.
+//!
+//! A tag counts as worn once enough of its recent reads had problems, since
+//! failing tags get worse quickly but a single bad read is usually just a
+//! visitor waving the fish about.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::json_store;
+use crate::uid_registry::format_uid;
+
+pub const WEAR_FILE_NAME: &str = "tag_wear.json";
+
+// How many reads back the recent problem rate looks
+const RECENT_READS: usize = 20;
+// Too few reads to tell a worn tag from bad luck
+const MIN_RECENT_READS: usize = 5;
+// Share of recent reads with problems that makes a tag worn
+const WORN_PROBLEM_RATE: f64 = 0.25;
+
+/// What went wrong while reading one tag
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReadStats {
+    /// Block reads that were tried again
+    pub retries: u32,
+    /// Block reads that still failed after retrying
+    pub failures: u32,
+    /// Response frames with a bad length or data checksum
+    pub checksum_errors: u32,
+}
+
+impl ReadStats {
+    fn had_problems(&self) -> bool {
+        self.retries > 0 || self.failures > 0 || self.checksum_errors > 0
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TagWear {
+    pub reads: u32,
+    /// Reads where some of the memory couldn't be read at all
+    pub failed_reads: u32,
+    pub retries: u32,
+    pub checksum_errors: u32,
+    /// NTAG NFC counter as of the last read, `None` if the tag doesn't have
+    /// it turned on (tags written by the kiosk do)
+    pub read_counter: Option<u32>,
+    /// Whether each of the last `RECENT_READS` reads had problems, oldest
+    /// first
+    recent: Vec<bool>,
+}
+
+impl TagWear {
+    fn recent_problem_rate(&self) -> f64 {
+        if self.recent.is_empty() {
+            return 0.0;
+        }
+        self.recent.iter().filter(|&&problem| problem).count() as f64 / self.recent.len() as f64
+    }
+
+    fn is_worn(&self) -> bool {
+        self.recent.len() >= MIN_RECENT_READS && self.recent_problem_rate() >= WORN_PROBLEM_RATE
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WearReportItem {
+    pub uid: String,
+    #[serde(flatten)]
+    pub wear: TagWear,
+    /// Share of the last few reads that had problems, 0 to 1
+    pub recent_problem_rate: f64,
+    pub worn: bool,
+}
+
+pub struct WearLog {
+    path: PathBuf,
+    tags: BTreeMap<String, TagWear>,
+}
+
+impl WearLog {
+    pub fn load(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            tags: json_store::load(path),
+        }
+    }
+
+    /// Records a read of the tag with UID `uid` and saves the log. Returns
+    /// `true` if the tag has just started looking worn.
+    pub fn record(
+        &mut self,
+        uid: &[u8],
+        stats: ReadStats,
+        read_counter: Option<u32>,
+    ) -> Result<bool, String> {
+        let wear = self.tags.entry(format_uid(uid)).or_default();
+        let was_worn = wear.is_worn();
+
+        wear.reads = wear.reads.saturating_add(1);
+        if stats.failures > 0 {
+            wear.failed_reads = wear.failed_reads.saturating_add(1);
+        }
+        wear.retries = wear.retries.saturating_add(stats.retries);
+        wear.checksum_errors = wear.checksum_errors.saturating_add(stats.checksum_errors);
+        if read_counter.is_some() {
+            wear.read_counter = read_counter;
+        }
+        wear.recent.push(stats.had_problems());
+        if wear.recent.len() > RECENT_READS {
+            wear.recent.remove(0);
+        }
+
+        let now_worn = wear.is_worn();
+        self.save()?;
+        Ok(now_worn && !was_worn)
+    }
+
+    /// Every tag, most recent problems first
+    pub fn report(&self) -> Vec<WearReportItem> {
+        let mut items: Vec<WearReportItem> = self
+            .tags
+            .iter()
+            .map(|(uid, wear)| WearReportItem {
+                uid: uid.clone(),
+                wear: wear.clone(),
+                recent_problem_rate: wear.recent_problem_rate(),
+                worn: wear.is_worn(),
+            })
+            .collect();
+        items.sort_by(|a, b| b.recent_problem_rate.total_cmp(&a.recent_problem_rate));
+        items
+    }
+
+    /// Logs every worn tag, e.g. at startup
+    pub fn log_worn(&self) {
+        for item in self.report().iter().filter(|item| item.worn) {
+            eprintln!("Scanner: {}", worn_message(&item.uid, &item.wear));
+        }
+    }
+
+    /// Log line for a worn tag
+    pub fn worn_message(&self, uid: &[u8]) -> Option<String> {
+        let uid = format_uid(uid);
+        let wear = self.tags.get(&uid)?;
+        Some(worn_message(&uid, wear))
+    }
+
+    fn save(&self) -> Result<(), String> {
+        json_store::save(&self.path, &self.tags)
+    }
+}
+
+fn worn_message(uid: &str, wear: &TagWear) -> String {
+    let problems = wear.recent.iter().filter(|&&problem| problem).count();
+    format!(
+        "Tag UID {} - Looks worn, {} of its last {} reads had errors, replace it",
+        uid,
+        problems,
+        wear.recent.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UID: [u8; 7] = [0x04, 0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6];
+
+    const CLEAN: ReadStats = ReadStats {
+        retries: 0,
+        failures: 0,
+        checksum_errors: 0,
+    };
+    const RETRIED: ReadStats = ReadStats {
+        retries: 1,
+        failures: 0,
+        checksum_errors: 0,
+    };
+
+    struct TempLog {
+        log: WearLog,
+        dir: PathBuf,
+    }
+
+    impl Drop for TempLog {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.dir).ok();
+        }
+    }
+
+    fn log(name: &str) -> TempLog {
+        let dir = std::env::temp_dir().join(format!("tag-wear-{}-{}", name, std::process::id()));
+        TempLog {
+            log: WearLog::load(&dir.join(WEAR_FILE_NAME)),
+            dir,
+        }
+    }
+
+    // whether each read turned the tag worn
+    fn reads(log: &mut WearLog, stats: &[ReadStats]) -> Vec<bool> {
+        stats
+            .iter()
+            .map(|&stats| log.record(&UID, stats, None).unwrap())
+            .collect()
+    }
+
+    fn worn(log: &WearLog) -> bool {
+        log.report()[0].worn
+    }
+
+    #[test]
+    fn needs_a_few_reads_first() {
+        let mut temp = log("min");
+        let log = &mut temp.log;
+        assert_eq!(reads(log, &[RETRIED; MIN_RECENT_READS - 1]), [false; 4]);
+        assert!(!worn(log));
+        assert!(log.record(&UID, RETRIED, None).unwrap());
+        assert!(worn(log));
+        // only the read that turned it worn says so
+        assert!(!log.record(&UID, RETRIED, None).unwrap());
+    }
+
+    #[test]
+    fn a_quarter_of_recent_reads() {
+        let mut temp = log("rate");
+        let log = &mut temp.log;
+        // 1 in 7
+        reads(log, &[CLEAN, CLEAN, CLEAN, RETRIED, CLEAN, CLEAN, CLEAN]);
+        assert!(!worn(log));
+        // 2 in 8
+        assert!(log.record(&UID, RETRIED, None).unwrap());
+        assert_eq!(log.report()[0].recent_problem_rate, 0.25);
+        // 2 in 9
+        log.record(&UID, CLEAN, None).unwrap();
+        assert!(!worn(log));
+        // and worn again
+        assert!(log.record(&UID, RETRIED, None).unwrap());
+    }
+
+    #[test]
+    fn only_the_last_reads_count() {
+        let mut temp = log("window");
+        let log = &mut temp.log;
+        reads(log, &[RETRIED; 5]);
+        reads(log, &[CLEAN; RECENT_READS - 5]);
+        // 5 in 20
+        assert!(worn(log));
+        log.record(&UID, CLEAN, None).unwrap();
+        // the first problem has rolled out of the window, 4 in 20
+        assert!(!worn(log));
+
+        let wear = &log.report()[0].wear;
+        assert_eq!(wear.recent.len(), RECENT_READS);
+        assert_eq!(wear.reads, RECENT_READS as u32 + 1);
+        assert_eq!(wear.retries, 5);
+    }
+
+    #[test]
+    fn totals_and_read_counter() {
+        let mut temp = log("totals");
+        let log = &mut temp.log;
+        let failed = ReadStats {
+            retries: 2,
+            failures: 1,
+            checksum_errors: 3,
+        };
+        log.record(&UID, failed, Some(41)).unwrap();
+        // the counter couldn't be read this time, keep the last one
+        log.record(&UID, failed, None).unwrap();
+
+        let wear = &WearLog::load(&temp.dir.join(WEAR_FILE_NAME)).report()[0].wear;
+        assert_eq!(
+            (
+                wear.reads,
+                wear.failed_reads,
+                wear.retries,
+                wear.checksum_errors
+            ),
+            (2, 2, 4, 6)
+        );
+        assert_eq!(wear.read_counter, Some(41));
+    }
+}
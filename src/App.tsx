@@ -36,7 +36,10 @@ function FishDisplayContent({ fish }: { fish: Fish }) {
         <h2 className="text-3xl font-bold text-primary border-b-2 border-border pb-2">
           {fish.name}
         </h2>
-        <FishEndangeredStatusBadge status={fish.endangered_status} />
+        <FishEndangeredStatusBadge
+          status={fish.endangered_status}
+          statusKey={fish.conservation_status}
+        />
         <div className="grid grid-cols-1 sm:grid-cols-2 gap-4">
           <FishInfoCard
             icon={<Ruler className="w-5 h-5" />}
@@ -70,7 +73,7 @@ function FishDisplayContent({ fish }: { fish: Fish }) {
       </div>
       <div className="flex flex-col space-y-4">
         <Card className="w-full h-[35vh] min-h-48 overflow-hidden relative p-0">
-          <img src={fish.image_path} alt={fish.name} className="w-full h-full object-cover" />
+          <img src={fish.image_url} alt={fish.name} className="w-full h-full object-cover" />
           <div className="absolute inset-0 bg-linear-to-t from-primary/50 to-transparent"></div>
         </Card>
         <Card className="p-4 bg-accent">
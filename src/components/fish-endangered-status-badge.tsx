@@ -32,8 +32,14 @@ function getFishEndangeredStatusInfo(status: string) {
   }
 }
 
-export function FishEndangeredStatusBadge({ status }: { status: string }) {
-  const { color, icon } = getFishEndangeredStatusInfo(status);
+export function FishEndangeredStatusBadge({
+  status,
+  statusKey = status,
+}: {
+  status: string;
+  statusKey?: string;
+}) {
+  const { color, icon } = getFishEndangeredStatusInfo(statusKey);
 
   return (
     <Badge
@@ -2,18 +2,61 @@ import { useState, useEffect } from "react";
 import { listen } from "@tauri-apps/api/event";
 import { invoke } from "@tauri-apps/api/core";
 
+export interface Measurement {
+  min: number | null;
+  max: number;
+  unit: string;
+}
+
+export interface Quantity {
+  value: number;
+  unit: string;
+}
+
+/** The individual fish that was scanned, only present on `fishData` events */
+export interface FishCatch {
+  length: Quantity;
+  weight: Quantity;
+  age: Quantity;
+  length_text: string;
+  weight_text: string;
+  age_text: string;
+  nickname?: string;
+  caught_at?: string;
+}
+
+export interface FishMedia {
+  kind: "image" | "video" | "narration";
+  url: string;
+  caption: string | null;
+  credit: string | null;
+  licence: string | null;
+}
+
 export interface Fish {
   id: number;
+  language: "en" | "fr" | "es";
   name: string;
+  scientific_name: string;
+  family: string;
+  order: string;
+  size: Measurement;
+  weight: Measurement;
+  lifespan: Measurement;
   average_size: string;
   average_weight: string;
   average_lifespan: string;
   habitat: string;
   diet: string;
   endangered_status: string;
+  conservation_status: string;
   blurb: string;
   image_path: string;
+  image_url: string;
   fun_fact: string;
+  media: FishMedia[];
+  related: number[];
+  catch?: FishCatch;
 }
 
 interface UseFishScannerOptions {